@@ -1,24 +1,96 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use crate::expr::LiteralValue;
+use crate::module::Context;
+use crate::heap;
 use std::rc::Rc;
 use std::cell::RefCell;
 
 pub struct Environment {
     values: HashMap<String, LiteralValue>,
+    constants: HashSet<String>,
     pub enclosing: Option<Rc<RefCell<Environment>>>,
+    context: Option<Rc<Context>>,
 }
 
 impl Environment {
     pub fn new() -> Self {
         Self {
             values: HashMap::new(),
+            constants: HashSet::new(),
             enclosing: None,
+            context: None,
         }
     }
+
+    pub fn with_context(context: Context) -> Self {
+        Self {
+            values: HashMap::new(),
+            constants: HashSet::new(),
+            enclosing: None,
+            context: Some(Rc::new(context)),
+        }
+    }
+
+    // A scope nested in `enclosing`, tracked so that cycles through it can
+    // be collected. It keeps its own handle on the context, since the
+    // limits are looked up on every step.
+    pub fn new_enclosed(enclosing: &Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
+        let mut environment = Self::new();
+        environment.context = enclosing.borrow().context();
+        environment.enclosing = Some(enclosing.clone());
+        let environment = Rc::new(RefCell::new(environment));
+        heap::track_environment(&environment);
+        environment
+    }
+
+    // The context of the script or module this scope belongs to. Scopes
+    // made with `new_enclosed` hold it themselves; others look through
+    // their enclosing chain.
+    pub fn context(&self) -> Option<Rc<Context>> {
+        match (&self.context, &self.enclosing) {
+            (Some(context), _) => Some(context.clone()),
+            (None, Some(env)) => env.borrow().context(),
+            (None, None) => None,
+        }
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &LiteralValue> {
+        self.values.values()
+    }
+
+    // The variables this scope itself declares.
+    pub fn entries(&self) -> impl Iterator<Item = (&String, &LiteralValue)> {
+        self.values.iter()
+    }
+
+    // Empties this scope, handing back what it held to be dropped.
+    pub fn clear(&mut self) -> Environment {
+        std::mem::replace(self, Self::new())
+    }
+
     pub fn define(&mut self, name: String, value: LiteralValue) {
         self.values.insert(name, value);
     }
 
+    pub fn define_constant(&mut self, name: String, value: LiteralValue) {
+        self.constants.insert(name.clone());
+        self.values.insert(name, value);
+    }
+
+    // Whether this scope itself declares `name` as a constant.
+    pub fn declares_constant(&self, name: &str) -> bool {
+        self.constants.contains(name)
+    }
+
+    // Whether the binding `name` resolves to is a constant.
+    pub fn is_constant(&self, name: &str) -> bool {
+        match (self.values.contains_key(name), &self.enclosing) {
+            (true, _) => self.constants.contains(name),
+            (false, Some(env)) => env.borrow().is_constant(name),
+            (false, None) => false,
+        }
+    }
+
     pub fn get(&self, name: &str) -> Option<LiteralValue> {
         let value = self.values.get(name);
 
@@ -51,4 +123,18 @@ mod tests {
     fn try_init() {
         let _env = Environment::new();
     }
+
+    #[test]
+    fn constants_resolve_through_scopes() {
+        let globals = Rc::new(RefCell::new(Environment::new()));
+        globals.borrow_mut().define_constant("LIMIT".to_string(), LiteralValue::Number(3.0));
+
+        let mut inner = Environment::new();
+        inner.enclosing = Some(globals.clone());
+        assert!(inner.is_constant("LIMIT"));
+        assert!(!inner.declares_constant("LIMIT"));
+
+        inner.define("LIMIT".to_string(), LiteralValue::Nil);
+        assert!(!inner.is_constant("LIMIT"));
+    }
 }
@@ -0,0 +1,124 @@
+use crate::expr::LiteralValue;
+
+// Why evaluation stopped early: an error raised by the interpreter itself,
+// or a value thrown by a `throw` statement. Both unwind to the nearest
+// enclosing `try`, or out of the script if there is none. `break` and
+// `continue` unwind the same way to their loop, but a `catch` lets them
+// pass; the resolver makes sure there is always a loop to stop them.
+// Running past a limit the host set unwinds past every `catch` too, so
+// the script can't carry on regardless.
+#[derive(Debug, Clone)]
+pub enum RuntimeError {
+    Error {
+        message: String,
+        line: usize,
+    },
+    Throw {
+        value: LiteralValue,
+        line: usize,
+    },
+    Break {
+        line: usize,
+    },
+    Continue {
+        line: usize,
+    },
+    Limit {
+        message: String,
+    },
+}
+
+impl RuntimeError {
+    pub fn error(line: usize, message: String) -> Self {
+        Self::Error { message, line }
+    }
+
+    // Natives don't know where they were called from and report line 0;
+    // the call site fills in its own line.
+    pub fn or_line(self, line: usize) -> Self {
+        match self {
+            Self::Error { message, line: 0 } => Self::Error { message, line },
+            Self::Throw { value, line: 0 } => Self::Throw { value, line },
+            other => other,
+        }
+    }
+
+    pub fn is_jump(&self) -> bool {
+        matches!(self, Self::Break { line: _ } | Self::Continue { line: _ })
+    }
+
+    // Whether a `catch` clause may handle this.
+    pub fn is_catchable(&self) -> bool {
+        matches!(self, Self::Error { message: _, line: _ } | Self::Throw { value: _, line: _ })
+    }
+
+    // The value a `catch` clause binds: thrown values are passed through as
+    // they are, interpreter errors become error objects.
+    pub fn into_value(self) -> LiteralValue {
+        match self {
+            Self::Error { message, line } => LiteralValue::Error { message, line },
+            Self::Throw { value, line: _ } => value,
+            Self::Break { line } => LiteralValue::Error { message: "'break' outside a loop".to_string(), line },
+            Self::Continue { line } => LiteralValue::Error { message: "'continue' outside a loop".to_string(), line },
+            Self::Limit { message } => LiteralValue::Error { message, line: 0 },
+        }
+    }
+}
+
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Error { message, line } => write!(f, "Line {}: {}", line, message),
+            Self::Throw { value, line } => write!(f, "Line {}: uncaught exception {}", line, value.to_string()),
+            Self::Break { line } => write!(f, "Line {}: 'break' outside a loop", line),
+            Self::Continue { line } => write!(f, "Line {}: 'continue' outside a loop", line),
+            Self::Limit { message } => write!(f, "{}", message),
+        }
+    }
+}
+
+// What the embedding API hands back when something goes wrong.
+#[derive(Debug, Clone)]
+pub enum LoxError {
+    // Scanning, parsing or resolving failed; one line per problem.
+    Compile(String),
+    Runtime(RuntimeError),
+    // A script file couldn't be read.
+    Io(String),
+    // The host asked for something the script doesn't have, such as an
+    // undefined global or a value of the wrong type.
+    Host(String),
+}
+
+impl From<RuntimeError> for LoxError {
+    fn from(error: RuntimeError) -> Self {
+        Self::Runtime(error)
+    }
+}
+
+impl std::fmt::Display for LoxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Compile(message) | Self::Io(message) | Self::Host(message) => write!(f, "{}", message),
+            Self::Runtime(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for LoxError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caught_values() {
+        let error = RuntimeError::error(3, "bad".to_string());
+        assert_eq!(error.to_string(), "Line 3: bad");
+        assert_eq!(error.into_value(), LiteralValue::Error { message: "bad".to_string(), line: 3 });
+
+        let thrown = RuntimeError::Throw { value: LiteralValue::Number(1.0), line: 7 };
+        assert_eq!(thrown.to_string(), "Line 7: uncaught exception 1");
+        assert_eq!(thrown.into_value(), LiteralValue::Number(1.0));
+    }
+}
@@ -1,6 +1,15 @@
 use crate::Token;
 use crate::scanner;
 use crate::environment::Environment;
+use crate::error::RuntimeError;
+use crate::interpreter::LoxFunction;
+use crate::generator::Generator;
+use crate::host::{self, HostObject};
+use crate::limits;
+use crate::heap;
+use crate::tasks::{self, Task};
+use crate::value::LoxValue;
+use crate::stmt::{FunctionSource, Parameters, Stmt};
 use std::rc::Rc;
 use std::cell::RefCell;
 
@@ -11,20 +20,114 @@ pub enum LiteralValue {
     True,
     False,
     Nil,
+    List(Rc<RefCell<Vec<LiteralValue>>>),
+    // String keys in the order they were first set.
+    Map(Rc<RefCell<Vec<(String, LiteralValue)>>>),
+    Error {
+        message: String,
+        line: usize,
+    },
+    Namespace {
+        name: String,
+        environment: Rc<RefCell<Environment>>,
+    },
     Callable {
         name: String,
-        arity: usize,
-        fun: Rc<dyn Fn(Rc<RefCell<Environment>>, &Vec<LiteralValue>) -> LiteralValue>,
+        arity: Arity,
+        fun: CallableFn,
+        // The Lox function it runs; None for natives.
+        function: Option<Rc<LoxFunction>>,
+    },
+    Enum(Rc<EnumType>),
+    EnumValue {
+        enum_type: Rc<EnumType>,
+        index: usize,
     },
+    Generator(Rc<Generator>),
+    Task(Rc<Task>),
+    HostObject(Rc<dyn HostObject>),
+}
+
+// What a callable runs: it gets the caller's environment and the arguments.
+pub type CallableFn = Rc<dyn Fn(Rc<RefCell<Environment>>, &Vec<LiteralValue>, &NamedArgs) -> Result<LiteralValue, RuntimeError>>;
+
+// A declared enum. Its values point back at it, so two enums that happen to
+// share variant names never compare equal.
+#[derive(Debug)]
+pub struct EnumType {
+    pub name: String,
+    pub variants: Vec<String>,
+}
+
+impl EnumType {
+    pub fn value(self: &Rc<Self>, index: usize) -> LiteralValue {
+        LiteralValue::EnumValue {
+            enum_type: self.clone(),
+            index,
+        }
+    }
+
+    pub fn values(self: &Rc<Self>) -> Vec<LiteralValue> {
+        (0..self.variants.len()).map(|index| self.value(index)).collect()
+    }
+}
+
+// Arguments passed as `name: value` at a call site, in source order.
+pub type NamedArgs = Vec<(Token, LiteralValue)>;
+
+// How many arguments a callable accepts. `max` is `None` when a rest
+// parameter soaks up any number of extra arguments.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Arity {
+    pub min: usize,
+    pub max: Option<usize>,
+}
+
+impl Arity {
+    pub fn exact(n: usize) -> Self {
+        Self {
+            min: n,
+            max: Some(n),
+        }
+    }
+
+    // For error messages: "1 argument", "1 to 2 arguments".
+    pub fn arguments(&self) -> String {
+        let count = self.max.unwrap_or(self.min);
+        format!("{} {}", self, if count == 1 { "argument" } else { "arguments" })
+    }
+
+    pub fn accepts(&self, n: usize) -> bool {
+        n >= self.min && self.max.is_none_or(|max| n <= max)
+    }
+}
+
+impl std::fmt::Display for Arity {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.max {
+            Some(max) if max == self.min => write!(f, "{}", max),
+            Some(max) => write!(f, "{} to {}", self.min, max),
+            None => write!(f, "at least {}", self.min),
+        }
+    }
 }
 
 impl PartialEq for LiteralValue {
     fn eq(&self, other: &Self) -> bool {
+        self.equals(other, &mut vec![])
+    }
+}
+
+impl LiteralValue {
+    // `comparing` holds the lists and maps being compared around this pair.
+    // Meeting one of them again means going round a cycle, and the pair is
+    // taken to be equal so far rather than compared forever.
+    fn equals(&self, other: &Self, comparing: &mut Vec<(usize, usize)>) -> bool {
         match (self, other) {
             (Self::Number(x), Self::Number(y)) => x == y,
             (
-                Self::Callable { name, arity, fun: _ }, 
-                Self::Callable { name: name2, arity: arity2, fun: _ }
+                Self::Callable { name, arity, fun: _, function: _ }, 
+                Self::Callable { name: name2, arity: arity2, fun: _, function: _ }
              ) => {
                 name == name2 && arity == arity2
             },
@@ -32,6 +135,45 @@ impl PartialEq for LiteralValue {
             (Self::True, Self::True) => true,
             (Self::False, Self::False) => true,
             (Self::Nil, Self::Nil) => true,
+            (Self::List(x), Self::List(y)) => {
+                let pair = (Rc::as_ptr(x) as usize, Rc::as_ptr(y) as usize);
+                if Rc::ptr_eq(x, y) || comparing.contains(&pair) {
+                    return true;
+                }
+                comparing.push(pair);
+                let (x, y) = (x.borrow(), y.borrow());
+                let equal = x.len() == y.len() && x.iter().zip(y.iter()).all(|(a, b)| a.equals(b, comparing));
+                comparing.pop();
+                equal
+            },
+            (Self::Map(x), Self::Map(y)) => {
+                let pair = (Rc::as_ptr(x) as usize, Rc::as_ptr(y) as usize);
+                if Rc::ptr_eq(x, y) || comparing.contains(&pair) {
+                    return true;
+                }
+                comparing.push(pair);
+                let (x, y) = (x.borrow(), y.borrow());
+                let equal = x.len() == y.len()
+                    && x.iter().zip(y.iter()).all(|((k1, v1), (k2, v2))| k1 == k2 && v1.equals(v2, comparing));
+                comparing.pop();
+                equal
+            },
+            (
+                Self::Error { message, line },
+                Self::Error { message: message2, line: line2 }
+            ) => message == message2 && line == line2,
+            (
+                Self::Namespace { name: _, environment },
+                Self::Namespace { name: _, environment: environment2 }
+            ) => Rc::ptr_eq(environment, environment2),
+            (Self::Enum(x), Self::Enum(y)) => Rc::ptr_eq(x, y),
+            (Self::Generator(x), Self::Generator(y)) => Rc::ptr_eq(x, y),
+            (Self::Task(x), Self::Task(y)) => Rc::ptr_eq(x, y),
+            (Self::HostObject(x), Self::HostObject(y)) => Rc::ptr_eq(x, y),
+            (
+                Self::EnumValue { enum_type, index },
+                Self::EnumValue { enum_type: enum_type2, index: index2 }
+            ) => Rc::ptr_eq(enum_type, enum_type2) && index == index2,
             _ => false,
         }
     }
@@ -58,15 +200,406 @@ fn unwrap_as_string(literal: Option<scanner::LiteralValue>) -> String {
     }
 }
 
+// Every integer below 2^24 has an f32 of its own; from there on they are
+// rounded to their neighbours, so bitwise operators refuse operands and
+// results outside it rather than quietly losing bits.
+const MAX_EXACT_INTEGER: i64 = (1 << f32::MANTISSA_DIGITS) - 1;
+
+// Bitwise operators only make sense on whole numbers, so the f32 is checked
+// for a fractional part before being converted to an i64.
+fn as_integer(value: &LiteralValue, operator: &Token) -> Result<i64, RuntimeError> {
+    match value {
+        LiteralValue::Number(x) if x.is_finite() && x.fract() == 0.0 => match x.abs() <= MAX_EXACT_INTEGER as f32 {
+            true => Ok(*x as i64),
+            false => Err(out_of_range(operator, format!("operand {}", x))),
+        },
+        other => Err(RuntimeError::error(operator.line_num, format!(
+            "'{}' is only defined for integers, got {}",
+            operator.lexeme,
+            other.to_string()
+        ))),
+    }
+}
+
+// Shifts are done in an i128, which holds any operand shifted by up to 63.
+fn from_integer(result: i128, operator: &Token) -> Result<LiteralValue, RuntimeError> {
+    if result.abs() <= MAX_EXACT_INTEGER as i128 {
+        return Ok(LiteralValue::Number(result as f32));
+    }
+    Err(out_of_range(operator, format!("result {}", result)))
+}
+
+fn out_of_range(operator: &Token, what: String) -> RuntimeError {
+    RuntimeError::error(operator.line_num, format!(
+        "'{}' only works on integers between -{} and {}, {} is out of range",
+        operator.lexeme, MAX_EXACT_INTEGER, MAX_EXACT_INTEGER, what
+    ))
+}
+
+fn evaluate_bitwise(left: &LiteralValue, operator: &Token, right: &LiteralValue) -> Result<LiteralValue, RuntimeError> {
+    use crate::scanner::TokenType::*;
+
+    let x = as_integer(left, operator)? as i128;
+    let y = as_integer(right, operator)? as i128;
+
+    let result = match operator.token_type {
+        Ampersand => x & y,
+        Pipe => x | y,
+        Caret => x ^ y,
+        LessLess | GreaterGreater => {
+            if !(0..64).contains(&y) {
+                return Err(RuntimeError::error(operator.line_num, format!("shift amount {} is out of range", y)));
+            }
+            if operator.token_type == LessLess { x << y } else { x >> y }
+        },
+        ttype => return Err(RuntimeError::error(operator.line_num, format!("{} is not a bitwise operator", ttype))),
+    };
+
+    from_integer(result, operator)
+}
+
+fn evaluate_binary(left: &LiteralValue, operator: &Token, right: &LiteralValue) -> Result<LiteralValue, RuntimeError> {
+    use crate::scanner::TokenType::*;
+
+    if matches!(operator.token_type, Ampersand | Pipe | Caret | LessLess | GreaterGreater) {
+        return evaluate_bitwise(left, operator, right);
+    }
+
+    match (left, operator.token_type, right) {
+        (LiteralValue::Number(x),       Plus,           LiteralValue::Number(y)) => Ok(LiteralValue::Number(x + y)),
+        (LiteralValue::Number(x),       Minus,          LiteralValue::Number(y)) => Ok(LiteralValue::Number(x - y)),
+        (LiteralValue::Number(x),       Star,           LiteralValue::Number(y)) => Ok(LiteralValue::Number(x * y)),
+        (LiteralValue::Number(x),       Slash,          LiteralValue::Number(y)) => Ok(LiteralValue::Number(x / y)),
+        (LiteralValue::Number(x),       Percent,        LiteralValue::Number(y)) => Ok(LiteralValue::Number(x % y)),
+        (LiteralValue::Number(x),       Greater,        LiteralValue::Number(y)) => Ok(LiteralValue::from_bool(x > y)),
+        (LiteralValue::Number(x),       GreaterEqual,   LiteralValue::Number(y)) => Ok(LiteralValue::from_bool(x >= y)),
+        (LiteralValue::Number(x),       Less,           LiteralValue::Number(y)) => Ok(LiteralValue::from_bool(x < y)),
+        (LiteralValue::Number(x),       LessEqual,      LiteralValue::Number(y)) => Ok(LiteralValue::from_bool(x <= y)),
+        (LiteralValue::Number(x),       BangEqual,      LiteralValue::Number(y)) => Ok(LiteralValue::from_bool(x != y)),
+        (LiteralValue::Number(x),       EqualEqual,     LiteralValue::Number(y)) => Ok(LiteralValue::from_bool(x == y)),
+
+        (LiteralValue::StringValue(_),  op,             LiteralValue::Number(_)) => Err(RuntimeError::error(operator.line_num, format!("'{}' is not defined for string and number", op))),
+        (LiteralValue::Number(_),       op,             LiteralValue::StringValue(_)) => Err(RuntimeError::error(operator.line_num, format!("'{}' is not defined for number and string", op))),
+
+        (LiteralValue::StringValue(s1), Plus,           LiteralValue::StringValue(s2)) => Ok(LiteralValue::StringValue(format!("{}{}", s1,s2))),
+        (LiteralValue::StringValue(s1), EqualEqual,     LiteralValue::StringValue(s2)) => Ok(LiteralValue::from_bool(s1 == s2)),
+        (LiteralValue::StringValue(s1), BangEqual,      LiteralValue::StringValue(s2)) => Ok(LiteralValue::from_bool(s1 != s2)),
+
+        (LiteralValue::StringValue(s1), Greater,        LiteralValue::StringValue(s2)) => Ok(LiteralValue::from_bool(s1 > s2)),
+        (LiteralValue::StringValue(s1), GreaterEqual,   LiteralValue::StringValue(s2)) => Ok(LiteralValue::from_bool(s1 >= s2)),
+        (LiteralValue::StringValue(s1), Less,           LiteralValue::StringValue(s2)) => Ok(LiteralValue::from_bool(s1 < s2)),
+        (LiteralValue::StringValue(s1), LessEqual,      LiteralValue::StringValue(s2)) => Ok(LiteralValue::from_bool(s1 <= s2)),
+
+        (LiteralValue::EnumValue { enum_type: _, index: _ }, EqualEqual, y) => Ok(LiteralValue::from_bool(left == y)),
+        (LiteralValue::EnumValue { enum_type: _, index: _ }, BangEqual,  y) => Ok(LiteralValue::from_bool(left != y)),
+        (LiteralValue::HostObject(_), EqualEqual, y) => Ok(LiteralValue::from_bool(left == y)),
+        (LiteralValue::HostObject(_), BangEqual,  y) => Ok(LiteralValue::from_bool(left != y)),
+        (LiteralValue::List(_) | LiteralValue::Map(_), EqualEqual, y) => Ok(LiteralValue::from_bool(left == y)),
+        (LiteralValue::List(_) | LiteralValue::Map(_), BangEqual,  y) => Ok(LiteralValue::from_bool(left != y)),
+        (x, ttype, y) => Err(RuntimeError::error(operator.line_num, format!("{} is not implemented for operands {} and {}", ttype, x.to_string(), y.to_string())))
+    }
+}
+
+// The length of the string `left + right` makes, so it can be accounted
+// for before it is built.
+fn concat_size(left: &LiteralValue, operator: &Token, right: &LiteralValue) -> usize {
+    match (left, operator.token_type, right) {
+        (LiteralValue::StringValue(s1), scanner::TokenType::Plus, LiteralValue::StringValue(s2)) => s1.len() + s2.len(),
+        _ => 0,
+    }
+}
+
+// Maps the token of a compound assignment (`+=`) or an increment (`++`) onto
+// the plain binary operator it applies.
+fn binary_operator(operator: &Token) -> Token {
+    use crate::scanner::TokenType::*;
+
+    let (token_type, lexeme) = match operator.token_type {
+        PlusEqual | PlusPlus => (Plus, "+"),
+        MinusEqual | MinusMinus => (Minus, "-"),
+        StarEqual => (Star, "*"),
+        SlashEqual => (Slash, "/"),
+        PercentEqual => (Percent, "%"),
+        other => (other, operator.lexeme.as_str()),
+    };
+
+    Token::new(token_type, lexeme.to_string(), None, operator.line_num)
+}
+
+fn list_position(items: &[LiteralValue], index: &LiteralValue, bracket: &Token) -> Result<usize, RuntimeError> {
+    match index {
+        LiteralValue::Number(x) if x.fract() == 0.0 && *x >= 0.0 && (*x as usize) < items.len() => Ok(*x as usize),
+        LiteralValue::Number(x) => Err(RuntimeError::error(bracket.line_num, format!("index {} is out of range for a list of length {}", x, items.len()))),
+        other => Err(RuntimeError::error(bracket.line_num, format!("list index must be a number, got {}", other.to_type()))),
+    }
+}
+
+fn map_key<'a>(index: &'a LiteralValue, bracket: &Token) -> Result<&'a str, RuntimeError> {
+    match index {
+        LiteralValue::StringValue(key) => Ok(key),
+        other => Err(RuntimeError::error(bracket.line_num, format!("map key must be a string, got {}", other.to_type()))),
+    }
+}
+
+pub fn map_get(entries: &[(String, LiteralValue)], key: &str) -> Option<LiteralValue> {
+    entries.iter().find(|(k, _)| k == key).map(|(_, value)| value.clone())
+}
+
+// What storing `key` in `object` allocates: a new entry when `object` is
+// a map without that key yet, nothing otherwise.
+fn map_growth(object: &LiteralValue, key: &str) -> usize {
+    match object {
+        LiteralValue::Map(entries) if map_get(&entries.borrow(), key).is_none() => limits::entry_size(key),
+        _ => 0,
+    }
+}
+
+pub fn map_set(entries: &mut Vec<(String, LiteralValue)>, key: &str, value: LiteralValue) {
+    match entries.iter_mut().find(|(k, _)| k == key) {
+        Some(entry) => entry.1 = value,
+        None => entries.push((key.to_string(), value)),
+    }
+}
+
+// A missing key reads as nil through an index, but is an error as a
+// property, where it is more likely a typo.
+fn get_index(object: &LiteralValue, index: &LiteralValue, bracket: &Token) -> Result<LiteralValue, RuntimeError> {
+    match object {
+        LiteralValue::List(items) => {
+            let items = items.borrow();
+            let position = list_position(&items, index, bracket)?;
+            Ok(items[position].clone())
+        },
+        LiteralValue::Map(entries) => {
+            let key = map_key(index, bracket)?;
+            Ok(map_get(&entries.borrow(), key).unwrap_or(LiteralValue::Nil))
+        },
+        other => Err(RuntimeError::error(bracket.line_num, format!("{} cannot be indexed", other.to_type()))),
+    }
+}
+
+fn set_index(object: &LiteralValue, index: &LiteralValue, value: LiteralValue, bracket: &Token) -> Result<(), RuntimeError> {
+    match object {
+        LiteralValue::List(items) => {
+            let mut items = items.borrow_mut();
+            let position = list_position(&items, index, bracket)?;
+            items[position] = value;
+            Ok(())
+        },
+        LiteralValue::Map(entries) => {
+            let key = map_key(index, bracket)?;
+            map_set(&mut entries.borrow_mut(), key, value);
+            Ok(())
+        },
+        other => Err(RuntimeError::error(bracket.line_num, format!("{} cannot be indexed", other.to_type()))),
+    }
+}
+
+pub fn get_property(object: &LiteralValue, name: &Token) -> Result<LiteralValue, RuntimeError> {
+    match (object, name.lexeme.as_str()) {
+        (LiteralValue::Error { message, line: _ }, "message") => Ok(LiteralValue::StringValue(message.clone())),
+        (LiteralValue::Error { message: _, line }, "line") => Ok(LiteralValue::Number(*line as f32)),
+        (LiteralValue::Namespace { name: module, environment }, property) => {
+            match environment.borrow().get(property) {
+                Some(value) => Ok(value),
+                None => Err(RuntimeError::error(name.line_num, format!("module '{}' has no binding '{}'", module, property))),
+            }
+        },
+        (LiteralValue::Enum(enum_type), variant) => {
+            match enum_type.variants.iter().position(|v| v == variant) {
+                Some(index) => Ok(enum_type.value(index)),
+                None => Err(RuntimeError::error(name.line_num, format!("enum '{}' has no variant '{}'", enum_type.name, variant))),
+            }
+        },
+        (LiteralValue::EnumValue { enum_type, index }, "name") => Ok(LiteralValue::StringValue(enum_type.variants[*index].clone())),
+        (LiteralValue::EnumValue { enum_type: _, index }, "index") => Ok(LiteralValue::Number(*index as f32)),
+        // `next()` gives `{value: v, done: false}` for each value yielded,
+        // then `{value: nil, done: true}` once the body has finished, so a
+        // yielded nil can't be mistaken for the end.
+        (LiteralValue::Generator(generator), "next") => {
+            let generator = generator.clone();
+            Ok(LiteralValue::Callable {
+                name: "next".to_string(),
+                arity: Arity::exact(0),
+                fun: Rc::new(move |environment, _args: &Vec<LiteralValue>, _named: &NamedArgs| {
+                    let resumed = generator.resume()?;
+                    limits::allocate(&environment, limits::entry_size("value") + limits::entry_size("done"))?;
+                    let done = LiteralValue::from_bool(resumed.is_none());
+                    Ok(LiteralValue::map(vec![
+                        ("value".to_string(), resumed.unwrap_or(LiteralValue::Nil)),
+                        ("done".to_string(), done),
+                    ]))
+                }),
+                function: None,
+            })
+        },
+        (LiteralValue::Generator(generator), "done") => Ok(LiteralValue::from_bool(generator.is_done())),
+        (LiteralValue::Task(task), "done") => Ok(LiteralValue::from_bool(task.is_done())),
+        (LiteralValue::Task(task), "result") => match task.result() {
+            Some(result) => result,
+            None => Err(RuntimeError::error(name.line_num, format!("task {} has not finished", task.name))),
+        },
+        (LiteralValue::Map(entries), key) => match map_get(&entries.borrow(), key) {
+            Some(value) => Ok(value),
+            None => Err(RuntimeError::error(name.line_num, format!("map has no key '{}'", key))),
+        },
+        (LiteralValue::HostObject(object), property) => host::get_property(object, property).map_err(|e| RuntimeError::error(name.line_num, e)),
+        (other, property) => Err(RuntimeError::error(name.line_num, format!("{} has no property '{}'", other.to_type(), property))),
+    }
+}
+
+pub fn set_property(object: &LiteralValue, name: &Token, value: LiteralValue) -> Result<(), RuntimeError> {
+    match object {
+        LiteralValue::Map(entries) => {
+            map_set(&mut entries.borrow_mut(), &name.lexeme, value);
+            Ok(())
+        },
+        LiteralValue::HostObject(object) => object.set(&name.lexeme, LoxValue(value)).map_err(|e| RuntimeError::error(name.line_num, e)),
+        other => Err(RuntimeError::error(name.line_num, format!("cannot set property '{}' on {}", name.lexeme, other.to_type()))),
+    }
+}
+
+// Looks up the callee and evaluates the arguments of a call, checking the
+// argument count, but doesn't call anything yet.
+fn prepare_call(
+    callee: &Expr,
+    paren: &Token,
+    arguments: &[Expr],
+    named: &[(Token, Expr)],
+    environment: Rc<RefCell<Environment>>,
+) -> Result<(String, CallableFn, Vec<LiteralValue>, NamedArgs), RuntimeError> {
+    // look up function definition in environment
+    let callable = callee.evaluate(environment.clone())?;
+    match callable {
+        LiteralValue::Callable { name, arity, fun, function } => {
+            // Only Lox functions have parameter names to bind named
+            // arguments to; natives and methods get positional ones alone.
+            if let (Some((arg_name, _)), None) = (named.first(), &function) {
+                return Err(RuntimeError::error(arg_name.line_num, format!("Callable {} does not take named arguments", name)));
+            }
+            // Do some checking (correct number of args?)
+            // With named arguments the callable itself reports
+            // what is missing, unknown or given twice.
+            let too_many = arity.max.is_some_and(|max| arguments.len() > max);
+            if too_many || (named.is_empty() && !arity.accepts(arguments.len())) {
+                return Err(RuntimeError::error(paren.line_num, format!("Callable {} expected {} but got {}", name, arity.arguments(), arguments.len())));
+            }
+            // Evaluate arguments
+            let mut arg_vals = vec![];
+            for arg in arguments {
+                let val = arg.evaluate(environment.clone())?;
+                arg_vals.push(val);
+            }
+            let mut named_vals = vec![];
+            for (arg_name, arg) in named {
+                let val = arg.evaluate(environment.clone())?;
+                named_vals.push((arg_name.clone(), val));
+            }
+
+            Ok((name, fun, arg_vals, named_vals))
+        }
+        other => Err(RuntimeError::error(paren.line_num, format!("{} is not callable", other.to_type()))),
+    }
+}
+
+fn check_not_constant(name: &Token, environment: &Rc<RefCell<Environment>>) -> Result<(), RuntimeError> {
+    if environment.borrow().is_constant(&name.lexeme) {
+        return Err(RuntimeError::error(name.line_num, format!("cannot assign to constant '{}'", name.lexeme)));
+    }
+
+    Ok(())
+}
+
+// Reads the current value of an assignment target, lets `update` compute the
+// new one and stores it back. The target's sub-expressions (the list and the
+// index of `xs[i] += 1`) are evaluated exactly once. Returns (old, new).
+fn modify_target<F>(
+    target: &Expr,
+    operator: &Token,
+    environment: Rc<RefCell<Environment>>,
+    update: F,
+) -> Result<(LiteralValue, LiteralValue), RuntimeError>
+where
+    F: FnOnce(&LiteralValue) -> Result<LiteralValue, RuntimeError>,
+{
+    match target {
+        Expr::Variable { name } => {
+            check_not_constant(name, &environment)?;
+            let current = target.evaluate(environment.clone())?;
+            let new_value = update(&current)?;
+            environment.borrow_mut().assign(&name.lexeme, new_value.clone());
+
+            Ok((current, new_value))
+        },
+        Expr::Index { object, bracket, index } => {
+            let object = object.evaluate(environment.clone())?;
+            let index = index.evaluate(environment.clone())?;
+            let current = get_index(&object, &index, bracket)?;
+            let new_value = update(&current)?;
+            set_index(&object, &index, new_value.clone(), bracket)?;
+
+            Ok((current, new_value))
+        },
+        Expr::Get { object, name } => {
+            let object = object.evaluate(environment.clone())?;
+            let current = get_property(&object, name)?;
+            let new_value = update(&current)?;
+            set_property(&object, name, new_value.clone())?;
+
+            Ok((current, new_value))
+        },
+        _ => Err(RuntimeError::error(operator.line_num, "invalid assignment target.".to_string())),
+    }
+}
+
 impl LiteralValue {
     pub fn to_string(&self) -> String {
+        self.display(&mut vec![])
+    }
+
+    // `parents` are the lists and maps being printed around this value; one
+    // that contains itself prints as `[...]` or `{...}` the second time.
+    fn display(&self, parents: &mut Vec<usize>) -> String {
         match self {
             Self::Number(x) => x.to_string(),
             Self::StringValue(x) => format!("\"{}\"", &x),
             Self::True => "true".to_string(),
             Self::False => "false".to_string(),
             Self::Nil => "nil".to_string(),
-            Self::Callable { name, arity, fun: _ } => format!("{name}/{arity}"),
+            Self::List(items) => {
+                let address = Rc::as_ptr(items) as usize;
+                if parents.contains(&address) {
+                    return "[...]".to_string();
+                }
+                parents.push(address);
+                let items: Vec<String> = items.borrow().iter().map(|item| item.display(parents)).collect();
+                parents.pop();
+                format!("[{}]", items.join(", "))
+            },
+            Self::Map(entries) => {
+                let address = Rc::as_ptr(entries) as usize;
+                if parents.contains(&address) {
+                    return "{...}".to_string();
+                }
+                parents.push(address);
+                let entries: Vec<String> = entries.borrow().iter().map(|(key, value)| {
+                    let plain = key.chars().next().is_some_and(scanner::is_alpha) && key.chars().all(scanner::is_alpha_numeric);
+                    match plain {
+                        true => format!("{}: {}", key, value.display(parents)),
+                        false => format!("\"{}\": {}", key, value.display(parents)),
+                    }
+                }).collect();
+                parents.pop();
+                format!("{{{}}}", entries.join(", "))
+            },
+            Self::Error { message, line } => format!("Error at line {line}: {message}"),
+            Self::Namespace { name, environment: _ } => format!("<module {name}>"),
+            Self::Callable { name, arity, fun: _, function: _ } => format!("{name}/{arity}"),
+            Self::Enum(enum_type) => format!("<enum {}>", enum_type.name),
+            Self::EnumValue { enum_type, index } => format!("{}.{}", enum_type.name, enum_type.variants[*index]),
+            Self::Generator(generator) => format!("<generator {}>", generator.name),
+            Self::Task(task) => task.to_string(),
+            Self::HostObject(object) => object.display(),
         }
     }
 
@@ -77,7 +610,16 @@ impl LiteralValue {
             Self::True => "Boolean",
             Self::False => "Boolean",
             Self::Nil => "Nil",
-            Self::Callable { name: _, arity: _, fun: _} => "Callable",
+            Self::List(_) => "List",
+            Self::Map(_) => "Map",
+            Self::Error { message: _, line: _ } => "Error",
+            Self::Namespace { name: _, environment: _ } => "Module",
+            Self::Callable { name: _, arity: _, fun: _, function: _ } => "Callable",
+            Self::Enum(_) => "Enum",
+            Self::EnumValue { enum_type, index: _ } => &enum_type.name,
+            Self::Generator(_) => "Generator",
+            Self::Task(_) => "Task",
+            Self::HostObject(object) => object.type_name(),
         }
     }
 
@@ -93,6 +635,20 @@ impl LiteralValue {
         }
     }
 
+    // Lists and maps are made through these so the heap can collect cycles
+    // through them.
+    pub fn list(items: Vec<LiteralValue>) -> Self {
+        let items = Rc::new(RefCell::new(items));
+        heap::track_list(&items);
+        Self::List(items)
+    }
+
+    pub fn map(entries: Vec<(String, LiteralValue)>) -> Self {
+        let entries = Rc::new(RefCell::new(entries));
+        heap::track_map(&entries);
+        Self::Map(entries)
+    }
+
     pub fn from_bool(b: bool) -> Self {
         if b {
             Self::True
@@ -108,7 +664,16 @@ impl LiteralValue {
             Self::True => Self::False,
             Self::False => Self::True,
             Self::Nil => Self::True,
-            Self::Callable { name: _, arity: _, fun: _ } => panic!("cannot use callable as truthy value"),
+            Self::List(items) => if items.borrow().is_empty() { Self::True } else { Self::False },
+            Self::Map(entries) => if entries.borrow().is_empty() { Self::True } else { Self::False },
+            Self::Error { message: _, line: _ } => Self::False,
+            Self::Namespace { name: _, environment: _ } => Self::False,
+            Self::Enum(_) => Self::False,
+            Self::EnumValue { enum_type: _, index: _ } => Self::False,
+            Self::Generator(_) => Self::False,
+            Self::Task(_) => Self::False,
+            Self::HostObject(_) => Self::False,
+            Self::Callable { name: _, arity: _, fun: _, function: _ } => Self::False,
         }
     }
 
@@ -125,30 +690,85 @@ impl LiteralValue {
             Self::True => Self::True,
             Self::False => Self::False,
             Self::Nil => Self::False,
-            Self::Callable { name: _, arity: _, fun: _ } => panic!("cannot use callable as truthy value"),
+            Self::List(items) => if items.borrow().is_empty() { Self::False } else { Self::True },
+            Self::Map(entries) => if entries.borrow().is_empty() { Self::False } else { Self::True },
+            Self::Error { message: _, line: _ } => Self::True,
+            Self::Namespace { name: _, environment: _ } => Self::True,
+            Self::Enum(_) => Self::True,
+            Self::EnumValue { enum_type: _, index: _ } => Self::True,
+            Self::Generator(_) => Self::True,
+            Self::Task(_) => Self::True,
+            Self::HostObject(_) => Self::True,
+            Self::Callable { name: _, arity: _, fun: _, function: _ } => Self::True,
         }
     }
 }
 
 #[derive(Clone)]
 pub enum Expr {
+    AnonFunction {
+        params: Parameters,
+        body: Vec<Box<Stmt>>,
+        source: Rc<FunctionSource>,
+    },
     Assign {
         name: Token,
         value: Box<Expr>,
     },
+    Await {
+        keyword: Token,
+        value: Box<Expr>,
+    },
     Binary {
         left: Box<Expr>,
         operator: Token,
         right: Box<Expr>,
     },
+    CompoundAssign {
+        target: Box<Expr>,
+        operator: Token,
+        value: Box<Expr>,
+    },
     Call {
         callee: Box<Expr>,
         paren: Token,
         arguments: Vec<Expr>,
+        named: Vec<(Token, Expr)>,
+    },
+    Conditional {
+        condition: Box<Expr>,
+        then: Box<Expr>,
+        els: Box<Expr>,
+    },
+    Get {
+        object: Box<Expr>,
+        name: Token,
     },
     Grouping {
         expression: Box<Expr>,
     },
+    Index {
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+    },
+    IndexAssign {
+        object: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+        value: Box<Expr>,
+    },
+    List {
+        elements: Vec<Expr>,
+    },
+    Map {
+        entries: Vec<(String, Expr)>,
+    },
+    Set {
+        object: Box<Expr>,
+        name: Token,
+        value: Box<Expr>,
+    },
     Literal {
         value: LiteralValue,
     },
@@ -157,10 +777,19 @@ pub enum Expr {
         operator: Token,
         right: Box<Expr>,
     },
+    Spawn {
+        keyword: Token,
+        call: Box<Expr>,
+    },
     Unary {
         operator: Token,
         right: Box<Expr>,
     },
+    Update {
+        target: Box<Expr>,
+        operator: Token,
+        prefix: bool,
+    },
     Variable {
         name: Token,
     }
@@ -175,6 +804,10 @@ impl std::fmt::Debug for Expr {
 impl Expr {
     pub fn to_string(&self) -> String {
         match self {
+            Expr::AnonFunction { params, body: _, source: _ } => {
+                let params: Vec<&str> = params.names.iter().map(|p| p.lexeme.as_str()).collect();
+                format!("(fun ({}))", params.join(" "))
+            },
             Expr::Assign { name, value } => {
                 format!("({} = {})", &name.lexeme, value.to_string())
             }
@@ -185,38 +818,85 @@ impl Expr {
                     right.to_string()
                 )
             },
+            Expr::Conditional { condition, then, els } => {
+                format!("(? {} {} {})", condition.to_string(), then.to_string(), els.to_string())
+            },
+            Expr::Get { object, name } => format!("(. {} {})", object.to_string(), name.lexeme),
             Expr::Grouping { expression } => format!("(group {})", expression.to_string()),
+            Expr::Index { object, bracket: _, index } => {
+                format!("(index {} {})", object.to_string(), index.to_string())
+            },
+            Expr::IndexAssign { object, bracket: _, index, value } => {
+                format!("((index {} {}) = {})", object.to_string(), index.to_string(), value.to_string())
+            },
+            Expr::Set { object, name, value } => {
+                format!("((. {} {}) = {})", object.to_string(), name.lexeme, value.to_string())
+            },
+            Expr::List { elements } => {
+                let elements: Vec<String> = elements.iter().map(|e| e.to_string()).collect();
+                format!("(list {})", elements.join(" "))
+            },
+            Expr::Map { entries } => {
+                let entries: Vec<String> = entries.iter().map(|(key, value)| format!("({} {})", key, value.to_string())).collect();
+                format!("(map {})", entries.join(" "))
+            },
             Expr::Literal { value } => {
                 format!("{}", value.to_string())
             },
             Expr::Logical { left, operator, right } => format!("({} {} {})", operator.to_string(), left.to_string(), right.to_string()),
+            Expr::Await { keyword: _, value } => format!("(await {})", value.to_string()),
+            Expr::Spawn { keyword: _, call } => format!("(spawn {})", call.to_string()),
             Expr::Unary {operator, right } => {
                 let operator_str = operator.lexeme.clone();
                 let right_str = right.to_string();
                 format!("({} {})", operator_str, right_str)
             },
             Expr::Variable { name } => format!("(var {})", name.lexeme),
-            Expr::Call { callee, paren: _, arguments } => format!("({} {:?})", (*callee).to_string(), arguments),
+            Expr::CompoundAssign { target, operator, value } => {
+                format!("({} {} {})", target.to_string(), &operator.lexeme, value.to_string())
+            },
+            Expr::Update { target, operator, prefix } => {
+                if *prefix {
+                    format!("({} {})", &operator.lexeme, target.to_string())
+                }else {
+                    format!("({} {})", target.to_string(), &operator.lexeme)
+                }
+            },
+            Expr::Call { callee, paren: _, arguments, named } => {
+                if named.is_empty() {
+                    format!("({} {:?})", (*callee).to_string(), arguments)
+                }else {
+                    let named: Vec<String> = named.iter()
+                        .map(|(name, value)| format!("{}: {}", name.lexeme, value.to_string()))
+                        .collect();
+                    format!("({} {:?} {{{}}})", (*callee).to_string(), arguments, named.join(", "))
+                }
+            },
         }
     }
 
-    pub fn evaluate(&self, environment: Rc<RefCell<Environment>>) -> Result<LiteralValue, String> {
+    pub fn evaluate(&self, environment: Rc<RefCell<Environment>>) -> Result<LiteralValue, RuntimeError> {
         use crate::scanner::TokenType::*;
 
+        limits::step(&environment)?;
         match self {
+            Expr::AnonFunction { params, body, source } => {
+                Ok(LoxFunction::new("lambda", params, body, source, environment.clone()).into_callable())
+            },
             Expr::Assign { name, value } => {
+                check_not_constant(name, &environment)?;
                 let new_value = (*value).evaluate(environment.clone())?;
                 let assign_success = environment.borrow_mut().assign(&name.lexeme, new_value.clone());
                 if assign_success {
                     return Ok(new_value);
                 }
 
-                Err(format!("variable {} has not been declared", name.lexeme))
+                Err(RuntimeError::error(name.line_num, format!("variable {} has not been declared", name.lexeme)))
             },
             Expr::Variable{ name } => {
                 match environment.borrow().get(name.lexeme.as_ref()) {
                     Some(value) => Ok(value.clone()),
-                    None => Err(format!("Variable '{}' has not been declared", &name.lexeme))
+                    None => Err(RuntimeError::error(name.line_num, format!("Variable '{}' has not been declared", &name.lexeme)))
                 }
             },
             Expr::Literal { value } => Ok(value.clone()),
@@ -240,72 +920,132 @@ impl Expr {
                             right.evaluate(environment.clone())
                         }
                     },
-                    ttype => Err(format!("Invalid token in logical expression: {}", ttype)),
+                    QuestionQuestion => {
+                        let lhs_value = left.evaluate(environment.clone())?;
+                        if lhs_value == LiteralValue::Nil {
+                            right.evaluate(environment.clone())
+                        }else {
+                            Ok(lhs_value)
+                        }
+                    },
+                    ttype => Err(RuntimeError::error(operator.line_num, format!("Invalid token in logical expression: {}", ttype))),
+                }
+            }
+            Expr::Conditional { condition, then, els } => {
+                let truth_value = condition.evaluate(environment.clone())?;
+                if truth_value.is_truthy() == LiteralValue::True {
+                    then.evaluate(environment.clone())
+                }else {
+                    els.evaluate(environment.clone())
                 }
             }
+            Expr::Get { object, name } => {
+                let object = object.evaluate(environment.clone())?;
+                get_property(&object, name)
+            },
             Expr::Grouping { expression } => expression.evaluate(environment),
             Expr::Unary { operator, right } => {
                 let right = (*right).evaluate(environment)?;
 
                 match (&right, operator.token_type) {
                     (LiteralValue::Number(x), Minus) => return Ok(LiteralValue::Number(-x)),
-                    (_, Minus) => return Err(format!("minus not implemented for {}", right.to_type())),
+                    (_, Minus) => return Err(RuntimeError::error(operator.line_num, format!("minus not implemented for {}", right.to_type()))),
+                    (any, Tilde) => from_integer(!as_integer(any, operator)? as i128, operator),
                     (any, Bang) => Ok(any.is_falsy()),
-                    (_, ttype) => Err(format!("{} is not a valid unary operator", ttype)),
+                    (_, ttype) => Err(RuntimeError::error(operator.line_num, format!("{} is not a valid unary operator", ttype))),
                 }
             },
             Expr::Binary { left, operator, right } => {
                 let left: LiteralValue = left.evaluate(environment.clone())?;
                 let right = right.evaluate(environment.clone())?;
 
-                match (&left, operator.token_type, &right) {
-                    (LiteralValue::Number(x),       Plus,           LiteralValue::Number(y)) => Ok(LiteralValue::Number(x + y)),
-                    (LiteralValue::Number(x),       Minus,          LiteralValue::Number(y)) => Ok(LiteralValue::Number(x - y)),
-                    (LiteralValue::Number(x),       Star,           LiteralValue::Number(y)) => Ok(LiteralValue::Number(x * y)),
-                    (LiteralValue::Number(x),       Slash,          LiteralValue::Number(y)) => Ok(LiteralValue::Number(x / y)),
-                    (LiteralValue::Number(x),       Greater,        LiteralValue::Number(y)) => Ok(LiteralValue::from_bool(x > y)),
-                    (LiteralValue::Number(x),       GreaterEqual,   LiteralValue::Number(y)) => Ok(LiteralValue::from_bool(x >= y)),
-                    (LiteralValue::Number(x),       Less,           LiteralValue::Number(y)) => Ok(LiteralValue::from_bool(x < y)),
-                    (LiteralValue::Number(x),       LessEqual,      LiteralValue::Number(y)) => Ok(LiteralValue::from_bool(x <= y)),
-                    (LiteralValue::Number(x),       BangEqual,      LiteralValue::Number(y)) => Ok(LiteralValue::from_bool(x != y)),
-                    (LiteralValue::Number(x),       EqualEqual,     LiteralValue::Number(y)) => Ok(LiteralValue::from_bool(x == y)),
-
-                    (LiteralValue::StringValue(_),  op,             LiteralValue::Number(_)) => Err(format!("'{}' is not defined for string and number", op)),
-                    (LiteralValue::Number(_),       op,             LiteralValue::StringValue(_)) => Err(format!("'{}' is not defined for number and string", op)),
-
-                    (LiteralValue::StringValue(s1), Plus,           LiteralValue::StringValue(s2)) => Ok(LiteralValue::StringValue(format!("{}{}", s1,s2))),
-                    (LiteralValue::StringValue(s1), EqualEqual,     LiteralValue::StringValue(s2)) => Ok(LiteralValue::from_bool(s1 == s2)),
-                    (LiteralValue::StringValue(s1), BangEqual,      LiteralValue::StringValue(s2)) => Ok(LiteralValue::from_bool(s1 != s2)),
-
-                    (LiteralValue::StringValue(s1), Greater,        LiteralValue::StringValue(s2)) => Ok(LiteralValue::from_bool(s1 > s2)),
-                    (LiteralValue::StringValue(s1), GreaterEqual,   LiteralValue::StringValue(s2)) => Ok(LiteralValue::from_bool(s1 >= s2)),
-                    (LiteralValue::StringValue(s1), Less,           LiteralValue::StringValue(s2)) => Ok(LiteralValue::from_bool(s1 < s2)),
-                    (LiteralValue::StringValue(s1), LessEqual,      LiteralValue::StringValue(s2)) => Ok(LiteralValue::from_bool(s1 <= s2)),
-                    (x, ttype, y) => Err(format!("{} is not implemented for operands {} and {}", ttype, x.to_string(), y.to_string()))
+                limits::allocate(&environment, concat_size(&left, operator, &right))?;
+                evaluate_binary(&left, operator, &right)
+            },
+            Expr::CompoundAssign { target, operator, value } => {
+                let (_, new_value) = modify_target(target, operator, environment.clone(), |current| {
+                    let rhs = value.evaluate(environment.clone())?;
+                    let operator = binary_operator(operator);
+                    limits::allocate(&environment, concat_size(current, &operator, &rhs))?;
+                    evaluate_binary(current, &operator, &rhs)
+                })?;
+
+                Ok(new_value)
+            },
+            Expr::Update { target, operator, prefix } => {
+                let (old_value, new_value) = modify_target(target, operator, environment.clone(), |current| {
+                    evaluate_binary(current, &binary_operator(operator), &LiteralValue::Number(1.0))
+                })?;
+
+                if *prefix {
+                    Ok(new_value)
+                }else {
+                    Ok(old_value)
                 }
             },
-            Expr::Call { callee, paren: _, arguments} => {
-                // look up function definition in environment
-                let callable = (*callee).evaluate(environment.clone())?;
-                match callable {
-                    LiteralValue::Callable { name, arity, fun } => {
-                        // Do some checking (correct number of args?)
-                        if arguments.len() != arity {
-                            return Err(format!("Callable {} expected {} arguments but got {}", name, arity, arguments.len()));
-                        }
-                        // Evaluate arguments
-                        let mut arg_vals = vec![];
-                        for arg in arguments {
-                            let val = arg.evaluate(environment.clone())?;
-                            arg_vals.push(val);
-                        }
+            Expr::List { elements } => {
+                limits::allocate(&environment, limits::list_size(elements.len()))?;
+                let mut items = vec![];
+                for element in elements {
+                    items.push(element.evaluate(environment.clone())?);
+                }
 
-                        // Apply to arguments
-                        Ok(fun(environment.clone(), &arg_vals))
-                    }
-                    other => Err(format!("{} is not callable", other.to_type())),
+                Ok(LiteralValue::list(items))
+            },
+            Expr::Map { entries } => {
+                limits::allocate(&environment, entries.iter().map(|(key, _)| limits::entry_size(key)).sum())?;
+                let mut values = vec![];
+                for (key, value) in entries {
+                    values.push((key.clone(), value.evaluate(environment.clone())?));
                 }
-            }
+
+                Ok(LiteralValue::map(values))
+            },
+            Expr::Index { object, bracket, index } => {
+                let object = object.evaluate(environment.clone())?;
+                let index = index.evaluate(environment.clone())?;
+
+                get_index(&object, &index, bracket)
+            },
+            Expr::IndexAssign { object, bracket, index, value } => {
+                let object = object.evaluate(environment.clone())?;
+                let index = index.evaluate(environment.clone())?;
+                let value = value.evaluate(environment.clone())?;
+
+                if let LiteralValue::StringValue(key) = &index {
+                    limits::allocate(&environment, map_growth(&object, key))?;
+                }
+                set_index(&object, &index, value.clone(), bracket)?;
+                Ok(value)
+            },
+            Expr::Set { object, name, value } => {
+                let object = object.evaluate(environment.clone())?;
+                let value = value.evaluate(environment.clone())?;
+
+                limits::allocate(&environment, map_growth(&object, &name.lexeme))?;
+                set_property(&object, name, value.clone())?;
+                Ok(value)
+            },
+            Expr::Call { callee, paren, arguments, named } => {
+                let (_, fun, arg_vals, named_vals) = prepare_call(callee, paren, arguments, named, environment.clone())?;
+
+                // Apply to arguments
+                fun(environment.clone(), &arg_vals, &named_vals)
+                    .map_err(|e| e.or_line(paren.line_num))
+            },
+            Expr::Await { keyword, value } => {
+                let value = value.evaluate(environment.clone())?;
+                tasks::block_on(&environment, value, keyword.line_num)
+            },
+            Expr::Spawn { keyword, call } => {
+                match call.as_ref() {
+                    Expr::Call { callee, paren, arguments, named } => {
+                        let (name, fun, arg_vals, named_vals) = prepare_call(callee, paren, arguments, named, environment.clone())?;
+                        tasks::spawn(&environment, &name, fun, arg_vals, named_vals, keyword.line_num)
+                    },
+                    _ => Err(RuntimeError::error(keyword.line_num, "expected a call after 'spawn'".to_string())),
+                }
+            },
         }
     }
 
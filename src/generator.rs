@@ -0,0 +1,442 @@
+use crate::environment::Environment;
+use crate::error::RuntimeError;
+use crate::expr::{Expr, LiteralValue};
+use crate::interpreter::{bind_pattern, Interpreter, Iteration};
+use crate::scanner::Token;
+use crate::stmt::{BindingPattern, Stmt};
+use crate::tasks;
+use std::rc::Rc;
+use std::cell::RefCell;
+
+// The interpreter runs a statement to completion on the Rust stack, so it
+// can't stop halfway through a loop to hand out a value. A generator keeps
+// its own stack of frames instead, one for each statement it is partway
+// through. It only descends into statements that contain a `yield` or an
+// `await`; anything else is handed to the interpreter and runs as usual.
+pub struct Generator {
+    pub name: String,
+    line: usize,
+    environment: Rc<RefCell<Environment>>,
+    execution: RefCell<Execution>,
+}
+
+// Why the body stopped running.
+pub enum Resumed {
+    Yielded(LiteralValue),
+    // Waiting on a task; resume with its result.
+    Awaiting(LiteralValue),
+    // The body ran off its end, returning its trailing expression.
+    Finished(LiteralValue),
+}
+
+struct Execution {
+    frames: Vec<Frame>,
+    done: bool,
+}
+
+enum Frame {
+    // `returns` is set for the function body, whose trailing expression
+    // statement is its result.
+    Block {
+        statements: Vec<Box<Stmt>>,
+        index: usize,
+        environment: Rc<RefCell<Environment>>,
+        returns: bool,
+    },
+    Await {
+        into: AwaitInto,
+        line: usize,
+        environment: Rc<RefCell<Environment>>,
+        result: Option<Result<LiteralValue, RuntimeError>>,
+    },
+    // Also runs do-while loops, which skip the first check.
+    While {
+        condition: Expr,
+        body: Stmt,
+        increment: Option<Expr>,
+        environment: Rc<RefCell<Environment>>,
+        checks_first: bool,
+        entered: bool,
+    },
+    ForIn {
+        target: BindingPattern,
+        items: Iteration,
+        body: Stmt,
+        environment: Rc<RefCell<Environment>>,
+    },
+    Try {
+        catch: Option<(Token, Box<Stmt>)>,
+        finally: Option<Box<Stmt>>,
+        environment: Rc<RefCell<Environment>>,
+        phase: TryPhase,
+    },
+}
+
+// Where a try statement is up to. An error caught on the way out of its body
+// is parked in `Caught` or `Unwinding` until the frame runs again.
+enum TryPhase {
+    Body,
+    Caught(LiteralValue),
+    Catch,
+    Unwinding(RuntimeError),
+    Finally(Option<RuntimeError>),
+}
+
+// Where the result of a suspended `await` goes once it arrives.
+enum AwaitInto {
+    Discard,
+    Declare(BindingPattern),
+    Assign(Token),
+}
+
+// What the frame on top of the stack wants done next.
+enum Step {
+    Start(Box<Stmt>, Rc<RefCell<Environment>>),
+    Return(LiteralValue),
+    Pop,
+}
+
+// `await x;`, `var y = await x;` and `y = await x;` are the statements that
+// can suspend on an await. Returns where the result goes, the `await`
+// keyword and the awaited expression.
+fn await_statement(stmt: &Stmt) -> Option<(AwaitInto, &Token, &Expr)> {
+    match stmt {
+        Stmt::Expression { expression: Expr::Await { keyword, value } } => Some((AwaitInto::Discard, keyword, value)),
+        Stmt::Expression { expression: Expr::Assign { name, value } } => match value.as_ref() {
+            Expr::Await { keyword, value } => Some((AwaitInto::Assign(name.clone()), keyword, value)),
+            _ => None,
+        },
+        Stmt::Var { name, initializer: Expr::Await { keyword, value } } => {
+            Some((AwaitInto::Declare(BindingPattern::Name(name.clone())), keyword, value))
+        },
+        Stmt::Destructure { pattern, initializer: Expr::Await { keyword, value } } => {
+            Some((AwaitInto::Declare(pattern.clone()), keyword, value))
+        },
+        _ => None,
+    }
+}
+
+pub fn is_await_statement(stmt: &Stmt) -> bool {
+    await_statement(stmt).is_some()
+}
+
+fn new_scope(enclosing: &Rc<RefCell<Environment>>) -> Rc<RefCell<Environment>> {
+    Environment::new_enclosed(enclosing)
+}
+
+impl Generator {
+    pub fn new(name: &str, line: usize, body: &[Box<Stmt>], environment: Rc<RefCell<Environment>>) -> Self {
+        let frame = Frame::Block {
+            statements: body.to_vec(),
+            index: 0,
+            environment: environment.clone(),
+            returns: true,
+        };
+
+        Self {
+            name: name.to_string(),
+            line,
+            environment,
+            execution: RefCell::new(Execution {
+                frames: vec![frame],
+                done: false,
+            }),
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.execution.try_borrow().is_ok_and(|execution| execution.done)
+    }
+
+    // Runs the body up to its next `yield` and returns the value, or None
+    // once the body has finished. Outside the event loop an `await` just
+    // waits for its task here.
+    pub fn resume(&self) -> Result<Option<LiteralValue>, RuntimeError> {
+        let mut input = None;
+        loop {
+            match self.step(input)? {
+                Resumed::Yielded(value) => return Ok(Some(value)),
+                Resumed::Awaiting(value) => input = Some(tasks::block_on(&self.environment, value, self.line)),
+                Resumed::Finished(_) => return Ok(None),
+            }
+        }
+    }
+
+    // Runs the body until it yields, awaits or finishes. `input` is the
+    // result of the `await` it last stopped at. An error ends the generator.
+    pub fn step(&self, input: Option<Result<LiteralValue, RuntimeError>>) -> Result<Resumed, RuntimeError> {
+        let mut execution = match self.execution.try_borrow_mut() {
+            Ok(execution) => execution,
+            Err(_) => return Err(RuntimeError::error(self.line, format!("generator {} is already running", self.name))),
+        };
+        if execution.done {
+            return Ok(Resumed::Finished(LiteralValue::Nil));
+        }
+
+        if let (Some(Frame::Await { into: _, line: _, environment: _, result }), Some(input)) = (execution.frames.last_mut(), input) {
+            *result = Some(input);
+        }
+
+        let resumed = execution.run();
+        if !matches!(resumed, Ok(Resumed::Yielded(_)) | Ok(Resumed::Awaiting(_))) {
+            execution.done = true;
+            execution.frames.clear();
+        }
+        resumed
+    }
+}
+
+impl Execution {
+    fn run(&mut self) -> Result<Resumed, RuntimeError> {
+        let mut returned = LiteralValue::Nil;
+        loop {
+            let step = match self.frames.last_mut() {
+                Some(frame) => frame.step(),
+                None => return Ok(Resumed::Finished(returned)),
+            };
+
+            let result = match step {
+                Ok(Step::Start(stmt, environment)) => self.start(*stmt, environment),
+                Ok(Step::Return(value)) => {
+                    returned = value;
+                    Ok(None)
+                },
+                Ok(Step::Pop) => {
+                    self.frames.pop();
+                    Ok(None)
+                },
+                Err(error) => Err(error),
+            };
+
+            match result {
+                Ok(Some(resumed)) => return Ok(resumed),
+                Ok(None) => {},
+                Err(error) => self.unwind(error)?,
+            }
+        }
+    }
+
+    // Begins running `stmt`. Returns early if it yields or awaits straight
+    // away, otherwise pushes whatever frames it needs to carry on later.
+    fn start(&mut self, stmt: Stmt, environment: Rc<RefCell<Environment>>) -> Result<Option<Resumed>, RuntimeError> {
+        if !stmt.yields() {
+            Interpreter::with_environment(environment).interpret(vec![&stmt])?;
+            return Ok(None);
+        }
+
+        if let Some((into, keyword, value)) = await_statement(&stmt) {
+            let value = value.evaluate(environment.clone())?;
+            self.frames.push(Frame::Await {
+                into,
+                line: keyword.line_num,
+                environment,
+                result: None,
+            });
+            return Ok(Some(Resumed::Awaiting(value)));
+        }
+
+        match stmt {
+            Stmt::Yield { keyword: _, value } => {
+                return Ok(Some(Resumed::Yielded(value.evaluate(environment)?)));
+            },
+            Stmt::Block { statements } => {
+                self.frames.push(Frame::Block {
+                    statements,
+                    index: 0,
+                    environment: new_scope(&environment),
+                    returns: false,
+                });
+            },
+            Stmt::IfStmt { predicate, then, els } => {
+                if predicate.evaluate(environment.clone())?.is_truthy() == LiteralValue::True {
+                    return self.start(*then, environment);
+                }else if let Some(els) = els {
+                    return self.start(*els, environment);
+                }
+            },
+            Stmt::WhileStmt { condition, body, increment } => {
+                self.frames.push(Frame::While {
+                    condition,
+                    body: *body,
+                    increment,
+                    environment,
+                    checks_first: true,
+                    entered: false,
+                });
+            },
+            Stmt::DoWhile { body, condition } => {
+                self.frames.push(Frame::While {
+                    condition,
+                    body: *body,
+                    increment: None,
+                    environment,
+                    checks_first: false,
+                    entered: false,
+                });
+            },
+            Stmt::ForIn { target, iterable, body } => {
+                let iterable = iterable.evaluate(environment.clone())?;
+                self.frames.push(Frame::ForIn {
+                    items: Iteration::new(&iterable, target.line(), &environment)?,
+                    target,
+                    body: *body,
+                    environment,
+                });
+            },
+            Stmt::Match { keyword, subject, arms } => {
+                let subject = subject.evaluate(environment.clone())?;
+                let (arm_environment, body) = Interpreter::with_environment(environment)
+                    .select_arm(&keyword, &subject, &arms)?;
+                return self.start(body.clone(), arm_environment);
+            },
+            Stmt::Try { body, catch, finally } => {
+                self.frames.push(Frame::Try {
+                    catch,
+                    finally,
+                    environment: environment.clone(),
+                    phase: TryPhase::Body,
+                });
+                return self.start(*body, environment);
+            },
+            other => unreachable!("{} never yields", other.to_string()),
+        }
+
+        Ok(None)
+    }
+
+    // Pops frames until a try statement can deal with the error, or for
+    // `break` and `continue` until their loop. Hands the error back if
+    // nothing can, which ends the generator.
+    fn unwind(&mut self, error: RuntimeError) -> Result<(), RuntimeError> {
+        while let Some(frame) = self.frames.last_mut() {
+            // A loop carries on after `continue` and is done after `break`.
+            match error {
+                RuntimeError::Continue { line: _ } if frame.is_loop() => return Ok(()),
+                RuntimeError::Break { line: _ } if frame.is_loop() => {
+                    self.frames.pop();
+                    return Ok(());
+                },
+                _ => {},
+            }
+            if let Frame::Try { catch, finally, environment: _, phase } = frame {
+                match phase {
+                    TryPhase::Body if catch.is_some() && error.is_catchable() => {
+                        *phase = TryPhase::Caught(error.into_value());
+                        return Ok(());
+                    },
+                    TryPhase::Body | TryPhase::Catch if finally.is_some() => {
+                        *phase = TryPhase::Unwinding(error);
+                        return Ok(());
+                    },
+                    _ => {},
+                }
+            }
+            self.frames.pop();
+        }
+
+        Err(error)
+    }
+}
+
+impl Frame {
+    fn is_loop(&self) -> bool {
+        matches!(
+            self,
+            Frame::While { condition: _, body: _, increment: _, environment: _, checks_first: _, entered: _ }
+                | Frame::ForIn { target: _, items: _, body: _, environment: _ }
+        )
+    }
+
+    fn step(&mut self) -> Result<Step, RuntimeError> {
+        match self {
+            Frame::Block { statements, index, environment, returns } => {
+                let last = *index + 1 == statements.len();
+                match statements.get(*index) {
+                    Some(stmt) => {
+                        *index += 1;
+                        match stmt.as_ref() {
+                            Stmt::Expression { expression } if *returns && last && !stmt.yields() => {
+                                Ok(Step::Return(expression.evaluate(environment.clone())?))
+                            },
+                            _ => Ok(Step::Start(stmt.clone(), environment.clone())),
+                        }
+                    },
+                    None => Ok(Step::Pop),
+                }
+            },
+            Frame::Await { into, line, environment, result } => {
+                let value = match result.take() {
+                    Some(result) => result?,
+                    None => return Err(RuntimeError::error(*line, "generator resumed while it waits on a task".to_string())),
+                };
+                match into {
+                    AwaitInto::Discard => {},
+                    AwaitInto::Declare(pattern) => bind_pattern(pattern, value, environment)?,
+                    AwaitInto::Assign(name) => {
+                        Expr::Assign {
+                            name: name.clone(),
+                            value: Box::new(Expr::Literal { value }),
+                        }.evaluate(environment.clone())?;
+                    },
+                }
+                Ok(Step::Pop)
+            },
+            Frame::While { condition, body, increment, environment, checks_first, entered } => {
+                if *entered {
+                    if let Some(increment) = increment {
+                        increment.evaluate(environment.clone())?;
+                    }
+                }
+                let check = *checks_first || *entered;
+                *entered = true;
+
+                if !check || condition.evaluate(environment.clone())?.is_truthy() == LiteralValue::True {
+                    Ok(Step::Start(Box::new(body.clone()), environment.clone()))
+                }else {
+                    Ok(Step::Pop)
+                }
+            },
+            Frame::ForIn { target, items, body, environment } => {
+                match items.next()? {
+                    Some(item) => {
+                        let item_environment = new_scope(environment);
+                        bind_pattern(target, item, &item_environment)?;
+                        Ok(Step::Start(Box::new(body.clone()), item_environment))
+                    },
+                    None => Ok(Step::Pop),
+                }
+            },
+            Frame::Try { catch, finally, environment, phase } => {
+                match std::mem::replace(phase, TryPhase::Catch) {
+                    TryPhase::Caught(value) => {
+                        let (name, handler) = catch.as_ref().expect("only a try with a catch clause catches");
+                        let catch_environment = new_scope(environment);
+                        catch_environment.borrow_mut().define(name.lexeme.clone(), value);
+                        Ok(Step::Start(handler.clone(), catch_environment))
+                    },
+                    TryPhase::Unwinding(error) => {
+                        let finally = finally.as_ref().expect("only a try with a finally clause unwinds");
+                        *phase = TryPhase::Finally(Some(error));
+                        Ok(Step::Start(finally.clone(), environment.clone()))
+                    },
+                    TryPhase::Body | TryPhase::Catch => match finally {
+                        Some(finally) => {
+                            *phase = TryPhase::Finally(None);
+                            Ok(Step::Start(finally.clone(), environment.clone()))
+                        },
+                        None => Ok(Step::Pop),
+                    },
+                    // The finally clause is done; carry on with whatever
+                    // error was waiting for it.
+                    TryPhase::Finally(pending) => {
+                        *phase = TryPhase::Finally(None);
+                        match pending {
+                            Some(error) => Err(error),
+                            None => Ok(Step::Pop),
+                        }
+                    },
+                }
+            },
+        }
+    }
+}
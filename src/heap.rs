@@ -0,0 +1,362 @@
+use crate::environment::Environment;
+use crate::expr::{CallableFn, LiteralValue};
+use crate::limits::{self, Limits};
+use crate::native::native_fn;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::{Rc, Weak};
+
+// Values are reference counted, which frees everything except cycles: a
+// function kept in a variable of the scope it closes over, or a list that
+// contains itself. The heap remembers every scope, list, map and function
+// made on this thread so those cycles can be found and broken.
+//
+// It works out which of them are still in use from the reference counts
+// alone. A reference the collector can't see, such as one held by Rust
+// code, a generator or a host object, keeps its target alive, so it never
+// frees anything that might still be used; it only misses cycles running
+// through something it can't look inside.
+
+// Don't bother collecting until this many objects are tracked.
+const MIN_COLLECTION: usize = 1000;
+
+thread_local! {
+    static HEAP: RefCell<Heap> = RefCell::new(Heap::default());
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GcStats {
+    // Objects tracked since the last collection, or that survived it.
+    pub tracked: usize,
+    pub collections: usize,
+    // Objects freed by all collections so far.
+    pub freed: usize,
+}
+
+#[derive(Default)]
+struct Heap {
+    environments: Vec<WeakEnvironment>,
+    lists: Vec<Weak<RefCell<Vec<LiteralValue>>>>,
+    maps: Vec<Weak<MapCell>>,
+    // Functions, with the scope they close over.
+    functions: Vec<(Weak<FunctionCell>, WeakEnvironment)>,
+    // How many survived the last collection.
+    survived: usize,
+    stats: GcStats,
+}
+
+type FunctionCell = <CallableFn as std::ops::Deref>::Target;
+type WeakEnvironment = Weak<RefCell<Environment>>;
+type MapCell = RefCell<Vec<(String, LiteralValue)>>;
+
+pub fn track_environment(environment: &Rc<RefCell<Environment>>) {
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.environments.push(Rc::downgrade(environment));
+        heap.stats.tracked += 1;
+    });
+}
+
+pub fn track_list(items: &Rc<RefCell<Vec<LiteralValue>>>) {
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.lists.push(Rc::downgrade(items));
+        heap.stats.tracked += 1;
+    });
+}
+
+pub fn track_map(entries: &Rc<RefCell<Vec<(String, LiteralValue)>>>) {
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.maps.push(Rc::downgrade(entries));
+        heap.stats.tracked += 1;
+    });
+}
+
+pub fn track_function(fun: &CallableFn, closure: &Rc<RefCell<Environment>>) {
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.functions.push((Rc::downgrade(fun), Rc::downgrade(closure)));
+        heap.stats.tracked += 1;
+    });
+}
+
+pub fn stats() -> GcStats {
+    HEAP.with(|heap| heap.borrow().stats)
+}
+
+// Collects once the heap has doubled since the last collection.
+pub fn collect_if_due() {
+    let due = HEAP.with(|heap| {
+        let heap = heap.borrow();
+        heap.stats.tracked >= MIN_COLLECTION.max(2 * heap.survived)
+    });
+    if due {
+        collect();
+    }
+}
+
+enum Object {
+    Environment(Rc<RefCell<Environment>>),
+    List(Rc<RefCell<Vec<LiteralValue>>>),
+    Map(Rc<RefCell<Vec<(String, LiteralValue)>>>),
+    Function(Rc<FunctionCell>, Weak<RefCell<Environment>>),
+}
+
+impl Object {
+    fn address(&self) -> usize {
+        match self {
+            Object::Environment(environment) => Rc::as_ptr(environment) as *const () as usize,
+            Object::List(items) => Rc::as_ptr(items) as *const () as usize,
+            Object::Map(entries) => Rc::as_ptr(entries) as *const () as usize,
+            Object::Function(fun, _) => Rc::as_ptr(fun) as *const () as usize,
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Object::Environment(environment) => Rc::strong_count(environment),
+            Object::List(items) => Rc::strong_count(items),
+            Object::Map(entries) => Rc::strong_count(entries),
+            Object::Function(fun, _) => Rc::strong_count(fun),
+        }
+    }
+
+    // The addresses of what this holds a reference to, or None if it is in
+    // use right now and can't be looked at.
+    fn references(&self) -> Option<Vec<usize>> {
+        let mut references = vec![];
+        match self {
+            Object::Environment(environment) => {
+                let environment = environment.try_borrow().ok()?;
+                if let Some(enclosing) = &environment.enclosing {
+                    references.push(Rc::as_ptr(enclosing) as *const () as usize);
+                }
+                environment.values().for_each(|value| value_references(value, &mut references));
+            },
+            Object::List(items) => items.try_borrow().ok()?.iter().for_each(|item| value_references(item, &mut references)),
+            Object::Map(entries) => entries.try_borrow().ok()?.iter().for_each(|(_, value)| value_references(value, &mut references)),
+            Object::Function(_, closure) => references.push(closure.as_ptr() as *const () as usize),
+        }
+        Some(references)
+    }
+
+    // Drops everything this holds, which breaks the cycles it is part of.
+    fn clear(&self) {
+        match self {
+            Object::Environment(environment) => {
+                let taken = environment.try_borrow_mut().map(|mut environment| environment.clear());
+                drop(taken);
+            },
+            Object::List(items) => {
+                let taken = items.try_borrow_mut().map(|mut items| std::mem::take(&mut *items));
+                drop(taken);
+            },
+            Object::Map(entries) => {
+                let taken = entries.try_borrow_mut().map(|mut entries| std::mem::take(&mut *entries));
+                drop(taken);
+            },
+            // Its closure is cleared instead.
+            Object::Function(_, _) => {},
+        }
+    }
+}
+
+fn value_references(value: &LiteralValue, references: &mut Vec<usize>) {
+    match value {
+        LiteralValue::List(items) => references.push(Rc::as_ptr(items) as *const () as usize),
+        LiteralValue::Map(entries) => references.push(Rc::as_ptr(entries) as *const () as usize),
+        LiteralValue::Callable { name: _, arity: _, fun, function: _ } => references.push(Rc::as_ptr(fun) as *const () as usize),
+        LiteralValue::Namespace { name: _, environment } => references.push(Rc::as_ptr(environment) as *const () as usize),
+        _ => {},
+    }
+}
+
+// `gc()` collects right away and returns how many objects it freed.
+pub fn natives() -> Vec<(&'static str, LiteralValue)> {
+    vec![("gc", native_fn("gc", || collect() as f64))]
+}
+
+// Frees every tracked object that is only reachable from other tracked
+// objects, and returns how many there were.
+pub fn collect() -> usize {
+    // Taken out of the heap while collecting, since freeing runs arbitrary
+    // drops.
+    let (environments, lists, maps, functions) = HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        (
+            std::mem::take(&mut heap.environments),
+            std::mem::take(&mut heap.lists),
+            std::mem::take(&mut heap.maps),
+            std::mem::take(&mut heap.functions),
+        )
+    });
+
+    let mut objects: Vec<Object> = vec![];
+    objects.extend(environments.iter().filter_map(|e| e.upgrade()).map(Object::Environment));
+    objects.extend(lists.iter().filter_map(|l| l.upgrade()).map(Object::List));
+    objects.extend(maps.iter().filter_map(|m| m.upgrade()).map(Object::Map));
+    objects.extend(functions.iter().filter_map(|(f, closure)| f.upgrade().map(|f| Object::Function(f, closure.clone()))));
+
+    let index: HashMap<usize, usize> = objects.iter().enumerate().map(|(i, object)| (object.address(), i)).collect();
+    let references: Vec<Option<Vec<usize>>> = objects.iter().map(|object| object.references()).collect();
+
+    // References from outside the heap: all of them, less the one held
+    // here, less those from other tracked objects.
+    let mut outside: Vec<usize> = objects.iter().map(|object| object.strong_count() - 1).collect();
+    for targets in references.iter().flatten() {
+        for target in targets {
+            if let Some(&i) = index.get(target) {
+                outside[i] = outside[i].saturating_sub(1);
+            }
+        }
+    }
+
+    // Anything referenced from outside, or in use, is live, and so is
+    // everything it reaches.
+    let mut live: Vec<bool> = (0..objects.len()).map(|i| outside[i] > 0 || references[i].is_none()).collect();
+    let mut pending: Vec<usize> = (0..objects.len()).filter(|&i| live[i]).collect();
+    while let Some(i) = pending.pop() {
+        for target in references[i].iter().flatten() {
+            if let Some(&j) = index.get(target) {
+                if !live[j] {
+                    live[j] = true;
+                    pending.push(j);
+                }
+            }
+        }
+    }
+
+    let mut freed = 0;
+    for (object, live) in objects.iter().zip(&live) {
+        if !live {
+            object.clear();
+            freed += 1;
+        }
+    }
+
+    let survivors: Vec<Object> = objects.into_iter().zip(live).filter(|(_, live)| *live).map(|(object, _)| object).collect();
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        for object in &survivors {
+            match object {
+                Object::Environment(environment) => heap.environments.push(Rc::downgrade(environment)),
+                Object::List(items) => heap.lists.push(Rc::downgrade(items)),
+                Object::Map(entries) => heap.maps.push(Rc::downgrade(entries)),
+                Object::Function(fun, closure) => heap.functions.push((Rc::downgrade(fun), closure.clone())),
+            }
+        }
+        heap.survived = heap.environments.len() + heap.lists.len() + heap.maps.len() + heap.functions.len();
+        heap.stats.tracked = heap.survived;
+        heap.stats.collections += 1;
+        heap.stats.freed += freed;
+    });
+
+    freed
+}
+
+// Roughly how many bytes the values reachable from the scopes of the engine
+// `limits` belongs to take up, by the estimates in `limits`. What only Rust
+// code holds on to, such as an intermediate result, isn't counted.
+pub fn used_by(limits: &Rc<Limits>) -> usize {
+    let environments: Vec<Rc<RefCell<Environment>>> = HEAP.with(|heap| {
+        heap.borrow().environments.iter().filter_map(|e| e.upgrade()).collect()
+    });
+
+    let mut seen = HashSet::new();
+    let mut used = 0;
+    for environment in environments {
+        let environment = match environment.try_borrow() {
+            Ok(environment) => environment,
+            Err(_) => continue,
+        };
+        if !environment.context().is_some_and(|context| Rc::ptr_eq(&context.limits, limits)) {
+            continue;
+        }
+        for (name, value) in environment.entries() {
+            used += limits::entry_size(name) + value_size(value, &mut seen);
+        }
+    }
+    used
+}
+
+// Lists and maps in `seen` were counted already, through another reference.
+fn value_size(value: &LiteralValue, seen: &mut HashSet<usize>) -> usize {
+    match value {
+        LiteralValue::StringValue(s) => s.len(),
+        LiteralValue::List(items) if seen.insert(Rc::as_ptr(items) as *const () as usize) => match items.try_borrow() {
+            Ok(items) => limits::list_size(items.len()) + items.iter().map(|item| value_size(item, seen)).sum::<usize>(),
+            Err(_) => 0,
+        },
+        LiteralValue::Map(entries) if seen.insert(Rc::as_ptr(entries) as *const () as usize) => match entries.try_borrow() {
+            Ok(entries) => entries.iter().map(|(key, value)| limits::entry_size(key) + value_size(value, seen)).sum(),
+            Err(_) => 0,
+        },
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::host::HostObject;
+    use crate::lox::Lox;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    // Notes when it is dropped, to see what a collection actually frees.
+    struct Probe {
+        dropped: Rc<Cell<bool>>,
+    }
+
+    impl HostObject for Probe {
+        fn type_name(&self) -> &str {
+            "Probe"
+        }
+    }
+
+    impl Drop for Probe {
+        fn drop(&mut self) {
+            self.dropped.set(true);
+        }
+    }
+
+    #[test]
+    fn frees_closures_kept_in_their_own_scope() {
+        let dropped = Rc::new(Cell::new(false));
+        let mut lox = Lox::new();
+        lox.set("probe", Rc::new(Probe { dropped: dropped.clone() })).unwrap();
+
+        lox.eval::<()>("fun make(p) { fun keep() { p; } nil; } make(probe); probe = nil;").unwrap();
+        assert!(!dropped.get());
+
+        let before = lox.gc_stats();
+        assert!(lox.gc() >= 2);
+        assert!(dropped.get());
+        assert_eq!(lox.gc_stats().collections, before.collections + 1);
+    }
+
+    #[test]
+    fn frees_collections_that_contain_themselves() {
+        let mut lox = Lox::new();
+        lox.gc();
+        let freed: f64 = lox.eval("var l = [nil]; l[0] = l; var m = {}; m.m = m; l = nil; m = nil; gc();").unwrap();
+        assert_eq!(freed, 2.0);
+    }
+
+    #[test]
+    fn keeps_what_is_still_reachable() {
+        let mut lox = Lox::new();
+        let source = "
+            var keep = {};
+            keep.self = keep;
+            fun counter() { var n = 0; fun inc() { n += 1; n; } inc; }
+            var c = counter();
+            fun run() { var local = [1]; local[0] = local; gc(); c() + c() + len(local); }
+            keep.self.self.x = run();
+            gc();
+            keep.x + c();
+        ";
+        lox.register_fn("len", |items: Vec<crate::expr::LiteralValue>| items.len() as f64);
+        assert_eq!(lox.eval::<f64>(source).unwrap(), 7.0);
+    }
+}
@@ -0,0 +1,167 @@
+use crate::expr::{Arity, LiteralValue, NamedArgs};
+use crate::error::RuntimeError;
+use crate::value::LoxValue;
+use std::any::Any;
+use std::rc::Rc;
+
+// A Rust value handed to scripts, such as a database handle or a request.
+// Scripts can read and write its properties and call its methods; every
+// one of those goes through this trait, and the value itself stays opaque.
+// Errors are plain messages; the interpreter adds the line.
+pub trait HostObject: Any {
+    fn type_name(&self) -> &str;
+
+    fn get(&self, name: &str) -> Result<LoxValue, String> {
+        Err(format!("{} has no property '{}'", self.type_name(), name))
+    }
+
+    // Takes `&self`: the object is shared, so anything settable needs
+    // interior mutability.
+    fn set(&self, name: &str, _value: LoxValue) -> Result<(), String> {
+        Err(format!("cannot set property '{}' on {}", name, self.type_name()))
+    }
+
+    // Whether `object.name` is a method rather than a property.
+    fn has_method(&self, _name: &str) -> bool {
+        false
+    }
+
+    fn call_method(&self, name: &str, _args: &[LoxValue]) -> Result<LoxValue, String> {
+        Err(format!("{} has no method '{}'", self.type_name(), name))
+    }
+
+    fn display(&self) -> String {
+        format!("<{}>", self.type_name())
+    }
+}
+
+impl dyn HostObject {
+    // The Rust value behind a host object, for natives that take one back.
+    pub fn downcast_ref<T: HostObject>(&self) -> Option<&T> {
+        (self as &dyn Any).downcast_ref()
+    }
+}
+
+// `object.name`: methods come back bound to the object, so calling them
+// later still reaches it. They take any number of arguments and check
+// them themselves.
+pub fn get_property(object: &Rc<dyn HostObject>, name: &str) -> Result<LiteralValue, String> {
+    if !object.has_method(name) {
+        return object.get(name).map(|value| value.0);
+    }
+
+    let object = object.clone();
+    let method = name.to_string();
+    Ok(LiteralValue::Callable {
+        name: format!("{}.{}", object.type_name(), name),
+        arity: Arity { min: 0, max: None },
+        fun: Rc::new(move |_env, args: &Vec<LiteralValue>, named: &NamedArgs| {
+            if let Some((arg_name, _)) = named.first() {
+                return Err(RuntimeError::error(arg_name.line_num, format!("method {} does not take named arguments", method)));
+            }
+            let args: Vec<LoxValue> = args.iter().cloned().map(LoxValue).collect();
+            object.call_method(&method, &args).map(|value| value.0).map_err(|e| RuntimeError::error(0, e))
+        }),
+        function: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HostObject;
+    use crate::lox::Lox;
+    use crate::output::OutputBuffer;
+    use std::cell::Cell;
+    use std::collections::HashMap;
+    use crate::value::{IntoLox, LoxValue};
+    use std::rc::Rc;
+
+    struct Request {
+        headers: HashMap<String, String>,
+        status: Cell<f32>,
+    }
+
+    impl HostObject for Request {
+        fn type_name(&self) -> &str {
+            "Request"
+        }
+
+        fn get(&self, name: &str) -> Result<LoxValue, String> {
+            match name {
+                "status" => self.status.get().into_lox(),
+                _ => Err(format!("Request has no property '{}'", name)),
+            }
+        }
+
+        fn set(&self, name: &str, value: LoxValue) -> Result<(), String> {
+            match (name, value.to::<f32>()) {
+                ("status", Ok(status)) => {
+                    self.status.set(status);
+                    Ok(())
+                },
+                ("status", Err(_)) => Err(format!("status must be a Number, got {}", value.type_name())),
+                _ => Err(format!("cannot set property '{}' on Request", name)),
+            }
+        }
+
+        fn has_method(&self, name: &str) -> bool {
+            name == "header"
+        }
+
+        fn call_method(&self, _name: &str, args: &[LoxValue]) -> Result<LoxValue, String> {
+            match args {
+                [key] => match key.to::<String>() {
+                    Ok(key) => self.headers.get(&key).cloned().into_lox(),
+                    Err(_) => Err("header expects a header name".to_string()),
+                },
+                _ => Err("header expects a header name".to_string()),
+            }
+        }
+    }
+
+    fn lox_with_request() -> (Lox, Rc<Request>) {
+        let request = Rc::new(Request {
+            headers: HashMap::from([("x".to_string(), "1".to_string())]),
+            status: Cell::new(200.0),
+        });
+        let mut lox = Lox::new();
+        lox.set("req", request.clone()).unwrap();
+        (lox, request)
+    }
+
+    #[test]
+    fn properties_and_methods() {
+        let (mut lox, request) = lox_with_request();
+        let output = OutputBuffer::new();
+        lox.set_stdout(output.clone());
+        assert_eq!(lox.eval::<String>("req.header(\"x\");").unwrap(), "1");
+        assert_eq!(lox.eval::<Option<String>>("req.header(\"y\");").unwrap(), None);
+        assert_eq!(lox.eval::<f64>("req.status = 404; req.status += 1; req.status;").unwrap(), 405.0);
+        assert_eq!(request.status.get(), 405.0);
+        assert_eq!(lox.eval::<String>("print req; \"\" + req.header(\"x\");").unwrap(), "1");
+        assert_eq!(output.contents(), "<Request>\n");
+    }
+
+    #[test]
+    fn errors_carry_the_line() {
+        let (mut lox, _) = lox_with_request();
+        let mut error = |source: &str| lox.eval::<()>(source).unwrap_err().to_string();
+        assert_eq!(error("req.body;"), "Line 1: Request has no property 'body'");
+        assert_eq!(error("\nreq.status = \"ok\";"), "Line 2: status must be a Number, got String");
+        assert_eq!(error("req.header(1);"), "Line 1: header expects a header name");
+        assert_eq!(error("var n = 1; n.x = 2;"), "Line 1: cannot set property 'x' on Number");
+    }
+
+    #[test]
+    fn natives_get_the_rust_value_back() {
+        let (mut lox, _) = lox_with_request();
+        lox.register_fn("header_count", |object: Rc<dyn HostObject>| {
+            match object.downcast_ref::<Request>() {
+                Some(request) => Ok(request.headers.len() as f64),
+                None => Err(format!("expected a Request, got {}", object.type_name())),
+            }
+        });
+        assert_eq!(lox.eval::<f64>("header_count(req);").unwrap(), 1.0);
+        assert_eq!(lox.eval::<String>("var r = req; r == req ? \"same\" : \"other\";").unwrap(), "same");
+    }
+}
@@ -1,54 +1,401 @@
 use crate::environment::Environment;
-use crate::stmt::Stmt; 
+use crate::error::RuntimeError;
+use crate::stmt::{BindingPattern, FunctionSource, MatchArm, Parameters, Pattern, Stmt};
+use crate::expr::{self, CallableFn, EnumType, LiteralValue, NamedArgs};
 use crate::scanner::Token;
-use crate::expr::LiteralValue;
+use crate::module::{self, Context};
+use crate::generator::Generator;
+use crate::sandbox;
+use crate::output;
+use crate::limits;
+use crate::heap;
+use crate::tasks;
 use std::rc::Rc;
 use std::cell::RefCell;
 
+// Everything a user-defined function needs to run: its parameters, its body
+// and the environment it was created in, which its body closes over.
+pub struct LoxFunction {
+    name: String,
+    params: Parameters,
+    body: Vec<Box<Stmt>>,
+    source: Rc<FunctionSource>,
+    closure: Rc<RefCell<Environment>>,
+    // The line a generator function was declared on, for its errors.
+    generator: Option<usize>,
+}
+
+impl LoxFunction {
+    pub fn new(name: &str, params: &Parameters, body: &[Box<Stmt>], source: &Rc<FunctionSource>, closure: Rc<RefCell<Environment>>) -> Self {
+        Self {
+            name: name.to_string(),
+            params: params.clone(),
+            body: body.to_vec(),
+            source: source.clone(),
+            closure,
+            generator: None,
+        }
+    }
+
+    // What a `fun` declaration binds its name to.
+    pub fn declared(name: &Token, params: &Parameters, body: &[Box<Stmt>], generator: bool, source: &Rc<FunctionSource>, closure: Rc<RefCell<Environment>>) -> LiteralValue {
+        let function = Self::new(&name.lexeme, params, body, source, closure);
+        match generator {
+            true => function.into_generator(name.line_num).into_callable(),
+            false => function.into_callable(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // The code that declared it: a `fun` declaration, a lambda or an arrow
+    // function.
+    pub fn source(&self) -> &FunctionSource {
+        &self.source
+    }
+
+    pub fn closure(&self) -> &Rc<RefCell<Environment>> {
+        &self.closure
+    }
+
+    // Calling a generator function binds its arguments and hands back a
+    // generator; the body only runs as values are asked for.
+    pub fn into_generator(mut self, line: usize) -> Self {
+        self.generator = Some(line);
+        self
+    }
+
+    pub fn into_callable(self) -> LiteralValue {
+        let name = self.name.clone();
+        let arity = self.params.arity();
+        let function = Rc::new(self);
+        let called = function.clone();
+        let fun: CallableFn = Rc::new(move |_env, args: &Vec<LiteralValue>, named: &NamedArgs| called.call(args, named));
+        heap::track_function(&fun, &function.closure);
+
+        LiteralValue::Callable { name, arity, fun, function: Some(function) }
+    }
+
+    // Binds the arguments to the parameters in a fresh environment and runs
+    // the body there. A trailing expression statement is the return value.
+    fn call(&self, args: &[LiteralValue], named: &NamedArgs) -> Result<LiteralValue, RuntimeError> {
+        let closure_interpreter = Interpreter::for_closure(self.closure.clone());
+        self.bind(&closure_interpreter.environment, args, named)?;
+        if let Some(line) = self.generator {
+            let generator = Generator::new(&self.name, line, &self.body, closure_interpreter.environment);
+            return Ok(LiteralValue::Generator(Rc::new(generator)));
+        }
+        self.run(closure_interpreter)
+    }
+
+    // Positional arguments fill parameters left to right, then named ones
+    // fill theirs. Whatever is still missing takes its default, evaluated now
+    // in the call's environment so it can refer to the parameters before it.
+    fn bind(&self, environment: &Rc<RefCell<Environment>>, args: &[LiteralValue], named: &NamedArgs) -> Result<(), RuntimeError> {
+        let names = &self.params.names;
+        let mut slots: Vec<Option<LiteralValue>> = names.iter()
+            .enumerate()
+            .map(|(i, _)| args.get(i).cloned())
+            .collect();
+
+        for (arg_name, value) in named {
+            let position = names.iter().position(|param| param.lexeme == arg_name.lexeme);
+            match position {
+                Some(i) if slots[i].is_some() => {
+                    return Err(RuntimeError::error(arg_name.line_num, format!("argument '{}' of {} was given more than once", arg_name.lexeme, self.name)));
+                },
+                Some(i) => slots[i] = Some(value.clone()),
+                None => {
+                    return Err(RuntimeError::error(arg_name.line_num, format!("{} has no parameter named '{}'", self.name, arg_name.lexeme)));
+                },
+            }
+        }
+
+        for (i, param) in names.iter().enumerate() {
+            let value = match (slots[i].take(), &self.params.defaults[i]) {
+                (Some(arg), _) => arg,
+                (None, Some(default)) => default.evaluate(environment.clone())?,
+                (None, None) => {
+                    // Only reachable through named arguments; positional
+                    // calls have their arity checked at the call site.
+                    let line = named.first().map_or(param.line_num, |(arg_name, _)| arg_name.line_num);
+                    return Err(RuntimeError::error(line, format!("{} is missing argument '{}'", self.name, param.lexeme)));
+                },
+            };
+            match &self.params.patterns[i] {
+                Some(pattern) => bind_pattern(pattern, value, environment)?,
+                None => environment.borrow_mut().define(param.lexeme.clone(), value),
+            }
+        }
+
+        if let Some(rest) = &self.params.rest {
+            let extra: Vec<LiteralValue> = args.iter().skip(names.len()).cloned().collect();
+            limits::allocate(environment, limits::list_size(extra.len()))?;
+            environment.borrow_mut().define(rest.lexeme.clone(), LiteralValue::list(extra));
+        }
+
+        Ok(())
+    }
+
+    fn run(&self, mut closure_interpreter: Interpreter) -> Result<LiteralValue, RuntimeError> {
+        let (last, init) = match self.body.split_last() {
+            Some(split) => split,
+            None => return Ok(LiteralValue::Nil),
+        };
+
+        for stmt in init {
+            closure_interpreter.interpret(vec![stmt])?;
+        }
+
+        match last.as_ref() {
+            Stmt::Expression { expression } => {
+                expression.evaluate(closure_interpreter.environment)
+            },
+            stmt => {
+                closure_interpreter.interpret(vec![stmt])?;
+                Ok(LiteralValue::Nil)
+            }
+        }
+    }
+}
+
+// Defines the names in `pattern` in `environment`, pulling them out of
+// `value`. List patterns need exactly as many items as names unless they
+// have a rest name; object patterns read each name as a property.
+pub fn bind_pattern(pattern: &BindingPattern, value: LiteralValue, environment: &Rc<RefCell<Environment>>) -> Result<(), RuntimeError> {
+    match pattern {
+        BindingPattern::Name(name) => {
+            if environment.borrow().declares_constant(&name.lexeme) {
+                return Err(RuntimeError::error(name.line_num, format!("cannot redeclare constant '{}'", name.lexeme)));
+            }
+            environment.borrow_mut().define(name.lexeme.clone(), value);
+        },
+        BindingPattern::List { bracket, elements, rest } => {
+            let items = match &value {
+                LiteralValue::List(items) => items.borrow().clone(),
+                other => return Err(RuntimeError::error(bracket.line_num, format!("cannot destructure {} as a list", other.to_type()))),
+            };
+
+            let fits = match rest {
+                Some(_) => items.len() >= elements.len(),
+                None => items.len() == elements.len(),
+            };
+            if !fits {
+                let expected = match rest {
+                    Some(_) => format!("at least {}", elements.len()),
+                    None => elements.len().to_string(),
+                };
+                return Err(RuntimeError::error(bracket.line_num, format!(
+                    "{} expects {} items but the list has {}",
+                    pattern.to_string(),
+                    expected,
+                    items.len()
+                )));
+            }
+
+            let mut items = items.into_iter();
+            for element in elements {
+                bind_pattern(element, items.next().unwrap_or(LiteralValue::Nil), environment)?;
+            }
+            if let Some(rest) = rest {
+                limits::allocate(environment, limits::list_size(items.len()))?;
+                let rest_items = LiteralValue::list(items.collect());
+                bind_pattern(&BindingPattern::Name(rest.clone()), rest_items, environment)?;
+            }
+        },
+        BindingPattern::Object { brace: _, names } => {
+            for name in names {
+                let property = expr::get_property(&value, name)?;
+                bind_pattern(&BindingPattern::Name(name.clone()), property, environment)?;
+            }
+        },
+    }
+
+    Ok(())
+}
+
+// Where a for-in loop gets its values. Lists are copied up front so the body
+// can change the list without upsetting the loop; generators are resumed for
+// one value at a time.
+pub enum Iteration {
+    Items(std::vec::IntoIter<LiteralValue>),
+    Generator(Rc<Generator>),
+}
+
+impl Iteration {
+    // `environment` is where the loop runs, for the lists made for a map.
+    pub fn new(value: &LiteralValue, line: usize, environment: &Rc<RefCell<Environment>>) -> Result<Self, RuntimeError> {
+        match value {
+            LiteralValue::List(items) => Ok(Self::Items(items.borrow().clone().into_iter())),
+            // A map gives its [key, value] pairs.
+            LiteralValue::Map(entries) => {
+                let size = entries.borrow().iter().map(|(key, _)| limits::list_size(2) + key.len()).sum();
+                limits::allocate(environment, size)?;
+                let pairs: Vec<LiteralValue> = entries.borrow().iter().map(|(key, value)| {
+                    let pair = vec![LiteralValue::StringValue(key.clone()), value.clone()];
+                    LiteralValue::list(pair)
+                }).collect();
+                Ok(Self::Items(pairs.into_iter()))
+            },
+            LiteralValue::Enum(enum_type) => Ok(Self::Items(enum_type.values().into_iter())),
+            LiteralValue::Generator(generator) => Ok(Self::Generator(generator.clone())),
+            other => Err(RuntimeError::error(line, format!("{} is not iterable", other.to_type()))),
+        }
+    }
+
+    pub fn next(&mut self) -> Result<Option<LiteralValue>, RuntimeError> {
+        match self {
+            Self::Items(items) => Ok(items.next()),
+            Self::Generator(generator) => generator.resume(),
+        }
+    }
+}
+
+// Takes one run of a loop body and decides whether the loop goes on:
+// `continue` ends just this run, `break` ends the loop.
+fn keep_looping(result: Result<(), RuntimeError>) -> Result<bool, RuntimeError> {
+    match result {
+        Ok(()) | Err(RuntimeError::Continue { line: _ }) => Ok(true),
+        Err(RuntimeError::Break { line: _ }) => Ok(false),
+        Err(error) => Err(error),
+    }
+}
+
+fn pattern_matches(pattern: &Pattern, subject: &LiteralValue, environment: Rc<RefCell<Environment>>, line: usize) -> Result<bool, RuntimeError> {
+    match pattern {
+        Pattern::Wildcard | Pattern::Binding(_) => Ok(true),
+        Pattern::Value(value) => Ok(value.evaluate(environment)? == *subject),
+        Pattern::Range { start, end, inclusive } => {
+            let start = start.evaluate(environment.clone())?;
+            let end = end.evaluate(environment)?;
+            match (start, end, subject) {
+                (LiteralValue::Number(start), LiteralValue::Number(end), LiteralValue::Number(x)) => {
+                    Ok(start <= *x && (*x < end || (*inclusive && *x == end)))
+                },
+                (LiteralValue::Number(_), LiteralValue::Number(_), _) => Ok(false),
+                (start, end, _) => Err(RuntimeError::error(line, format!("range pattern bounds must be numbers, got {} and {}", start.to_type(), end.to_type()))),
+            }
+        },
+    }
+}
+
 pub struct Interpreter {
     // globals: Environment,
     environment: Rc<RefCell<Environment>>,
 }
 
-fn clock_impl(_env: Rc<RefCell<Environment>>, _args: &Vec<LiteralValue>) -> LiteralValue {
-    use std::time::SystemTime;
-    let now = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
-    
-    // LiteralValue::Number(now as f32)
-    LiteralValue::StringValue(now.to_string())
-}
-
 impl Interpreter {
     pub fn new() -> Self {
-        let mut globals = Environment::new();
-        globals.define("clock".to_string(), LiteralValue::Callable { 
-            name: "clock".to_string(), 
-            arity: 0,
-            fun: Rc::new(clock_impl),
-        });
+        Self::with_context(Context::new(None))
+    }
+
+    pub fn with_context(context: Context) -> Self {
+        let mut globals = Environment::with_context(context);
+        for (name, native) in Self::natives() {
+            globals.define(name.to_string(), native);
+        }
+        let globals = Rc::new(RefCell::new(globals));
+        heap::track_environment(&globals);
         Self {
             // globals,
             // environment: Rc::new(RefCell::new(Environment::new())),
-            environment: Rc::new(RefCell::new(globals)),
+            environment: globals,
         }
     }
 
-    fn for_closure(parent: Rc<RefCell<Environment>>) -> Self {
-        let environment = Rc::new(RefCell::new(Environment::new()));
-        environment.borrow_mut().enclosing = Some(parent);
+    // The native functions every script starts out with.
+    pub fn natives() -> Vec<(&'static str, LiteralValue)> {
+        sandbox::natives().into_iter().chain(tasks::natives()).chain(heap::natives()).collect()
+    }
+
+    // Runs spawned tasks that are still pending, once the script is done.
+    pub fn run_tasks(&self) -> Result<(), RuntimeError> {
+        tasks::run_pending(&self.environment)
+    }
 
+    pub fn globals(&self) -> Rc<RefCell<Environment>> {
+        self.environment.clone()
+    }
+
+    // Runs statements directly in `environment` rather than a child of it.
+    pub fn with_environment(environment: Rc<RefCell<Environment>>) -> Self {
         Self {
             environment
         }
     }
 
-    pub fn interpret(&mut self, stmts: Vec<&Stmt>) -> Result<(), String> {
+    fn for_closure(parent: Rc<RefCell<Environment>>) -> Self {
+        Self {
+            environment: Environment::new_enclosed(&parent),
+        }
+    }
+
+    // Constants can't be shadowed by a later declaration in the same scope.
+    fn check_redeclaration(&self, name: &Token) -> Result<(), RuntimeError> {
+        if self.environment.borrow().declares_constant(&name.lexeme) {
+            return Err(RuntimeError::error(name.line_num, format!("cannot redeclare constant '{}'", name.lexeme)));
+        }
+
+        Ok(())
+    }
+
+    // Runs `stmt` in a new scope holding `name`, restoring the current scope
+    // afterwards even if it fails.
+    fn interpret_with(&mut self, target: &BindingPattern, value: LiteralValue, stmt: &Stmt) -> Result<(), RuntimeError> {
+        let environment = self.new_scope();
+        bind_pattern(target, value, &environment)?;
+        self.interpret_in(environment, stmt)
+    }
+
+    fn new_scope(&self) -> Rc<RefCell<Environment>> {
+        Environment::new_enclosed(&self.environment)
+    }
+
+    fn interpret_in(&mut self, environment: Rc<RefCell<Environment>>, stmt: &Stmt) -> Result<(), RuntimeError> {
+        let old_environment = self.environment.clone();
+        self.environment = environment;
+        let result = self.interpret(vec![stmt]);
+        self.environment = old_environment;
+        result
+    }
+
+    fn interpret_match(&mut self, keyword: &Token, subject: &LiteralValue, arms: &[MatchArm]) -> Result<(), RuntimeError> {
+        let (arm_environment, body) = self.select_arm(keyword, subject, arms)?;
+        self.interpret_in(arm_environment, body)
+    }
+
+    // Arms are tried top to bottom and the first whose pattern and guard
+    // both pass is picked, along with the scope holding its binding.
+    pub fn select_arm<'a>(&self, keyword: &Token, subject: &LiteralValue, arms: &'a [MatchArm]) -> Result<(Rc<RefCell<Environment>>, &'a Stmt), RuntimeError> {
+        for arm in arms {
+            if !pattern_matches(&arm.pattern, subject, self.environment.clone(), keyword.line_num)? {
+                continue;
+            }
+
+            let arm_environment = self.new_scope();
+            if let Pattern::Binding(name) = &arm.pattern {
+                arm_environment.borrow_mut().define(name.lexeme.clone(), subject.clone());
+            }
+            if let Some(guard) = &arm.guard {
+                if guard.evaluate(arm_environment.clone())?.is_truthy() != LiteralValue::True {
+                    continue;
+                }
+            }
+
+            return Ok((arm_environment, &arm.body));
+        }
+
+        Err(RuntimeError::error(keyword.line_num, format!("no match arm for {}", subject.to_string())))
+    }
+
+    pub fn interpret(&mut self, stmts: Vec<&Stmt>) -> Result<(), RuntimeError> {
         use crate::expr::LiteralValue;
 
         for stmt in stmts {
+            limits::step(&self.environment)?;
+            heap::collect_if_due();
             match stmt {
                 Stmt::Expression { expression } => {
                     expression.evaluate(
@@ -59,25 +406,77 @@ impl Interpreter {
                     let value = expression.evaluate(
                         self.environment.clone()
                     )?;
-                    println!("{}", value.to_string());
+                    output::print(&self.environment, &value.to_string())?;
                 },
                 Stmt::Var { name, initializer } => {
+                    self.check_redeclaration(name)?;
                     let value = initializer.evaluate(
                         self.environment.clone()
                     )?;
+                    limits::allocate(&self.environment, limits::entry_size(&name.lexeme))?;
 
                     self.environment.borrow_mut()
                         .define(name.lexeme.to_string(), value);
                 },
-                Stmt::Block { statements } => {
-                    let mut new_environment = Environment::new();
-                    new_environment.enclosing = Some(self.environment.clone());
+                Stmt::Destructure { pattern, initializer } => {
+                    let value = initializer.evaluate(
+                        self.environment.clone()
+                    )?;
 
+                    bind_pattern(pattern, value, &self.environment)?;
+                },
+                Stmt::Const { name, initializer } => {
+                    self.check_redeclaration(name)?;
+                    let value = initializer.evaluate(
+                        self.environment.clone()
+                    )?;
+                    limits::allocate(&self.environment, limits::entry_size(&name.lexeme))?;
+
+                    self.environment.borrow_mut()
+                        .define_constant(name.lexeme.to_string(), value);
+                },
+                Stmt::Block { statements } => {
                     let old_environment = self.environment.clone();
-                    self.environment = Rc::new(RefCell::new(new_environment));
+                    self.environment = self.new_scope();
                     let stmts = statements.into_iter().map(|b| b.as_ref()).collect();
-                    self.interpret(stmts)?;
+                    // Restore the scope even when the block fails, the error
+                    // may be caught further out.
+                    let result = self.interpret(stmts);
                     self.environment = old_environment;
+                    result?;
+                },
+                Stmt::Import { keyword, path, alias, names } => {
+                    let context = match self.environment.borrow().context() {
+                        Some(context) => context,
+                        None => return Err(RuntimeError::error(keyword.line_num, "imports are not available here".to_string())),
+                    };
+                    let module = module::import(&context, path, keyword.line_num)?;
+
+                    if let Some(alias) = alias {
+                        self.environment.borrow_mut().define(alias.lexeme.clone(), module.clone());
+                    }
+                    for name in names {
+                        let value = module::select(&module, &name.lexeme, name.line_num)?;
+                        self.environment.borrow_mut().define(name.lexeme.clone(), value);
+                    }
+                },
+                Stmt::Throw { keyword, value } => {
+                    let value = value.evaluate(self.environment.clone())?;
+                    return Err(RuntimeError::Throw { value, line: keyword.line_num });
+                },
+                Stmt::Try { body, catch, finally } => {
+                    let result = match (self.interpret(vec![body.as_ref()]), catch) {
+                        (Err(error), Some((name, handler))) if error.is_catchable() => {
+                            self.interpret_with(&BindingPattern::Name(name.clone()), error.into_value(), handler)
+                        },
+                        (result, _) => result,
+                    };
+
+                    // A failing finally block replaces whatever was pending.
+                    if let Some(finally) = finally {
+                        self.interpret(vec![finally.as_ref()])?;
+                    }
+                    result?;
                 },
                 Stmt::IfStmt { predicate, then, els } => {
 
@@ -90,70 +489,62 @@ impl Interpreter {
                         self.interpret(vec![els_stmt.as_ref()])?
                     }
                 }
-                Stmt::WhileStmt { condition, body } => {
+                Stmt::WhileStmt { condition, body, increment } => {
                     let mut flag = condition.evaluate(self.environment.clone())?;
                     while flag.is_truthy() == LiteralValue::True {
                         // TODO: do we to interpret statements in their own block
-                        self.interpret(vec![body.as_ref()])?;
+                        if !keep_looping(self.interpret(vec![body.as_ref()]))? {
+                            break;
+                        }
+                        if let Some(increment) = increment {
+                            increment.evaluate(self.environment.clone())?;
+                        }
                         flag = condition.evaluate(
                             self.environment.clone()
                         )?;
                     }
                 },
-                Stmt::Function { name, params, body } => {
-                    // Function decl
-                    let arity = params.len();
-                    // Function impl:
-                    // Bind list of input values to params
-                    // Add those bindings to the environment used to execute body
-                    // Then execute body
-
-                    let params: Vec<Token> = params.iter().map(|t| (*t).clone()).collect();
-
-                    let body: Vec<Box<Stmt>> = body.iter().map(|b| (*b).clone()).collect();
-                    
-                    let name_clone = name.clone();
-
-                    // TODO: make a struct that contains data for evaluation
-                    // and which implements Fn
-                    let fun_impl = move |parent_env, args: &Vec<LiteralValue>| {
-                    let mut closure_interpreter = Interpreter::for_closure(parent_env);
-                        for (i, arg) in args.iter().enumerate() {
-                            closure_interpreter.environment
-                                .borrow_mut()
-                                .define(params[i].lexeme.clone(), (*arg).clone()
-                            );
+                Stmt::DoWhile { body, condition } => {
+                    while keep_looping(self.interpret(vec![body.as_ref()]))? {
+                        if condition.evaluate(self.environment.clone())?.is_truthy() != LiteralValue::True {
+                            break;
                         }
-
-                        for i in 0..(body.len() - 1) {
-                            closure_interpreter
-                                .interpret(vec![&body[i]])
-                                .expect(
-                                    &format!("evaluating failed inside {}", 
-                                        name_clone.lexeme.clone())
-                                );
-                        }
-
-                        let value;
-                        match &body[body.len() - 1].as_ref() {
-                            &Stmt::Expression { expression } => {
-                                value = expression
-                                            .evaluate(closure_interpreter.environment)
-                                            .unwrap();
-                            },
-                            _ => todo!()
-                        }
-
-                        value
-                    }; // closure end
-
-                    let callable = LiteralValue::Callable {
+                    }
+                },
+                Stmt::Break { keyword } => return Err(RuntimeError::Break { line: keyword.line_num }),
+                Stmt::Continue { keyword } => return Err(RuntimeError::Continue { line: keyword.line_num }),
+                Stmt::Enum { name, variants } => {
+                    self.check_redeclaration(name)?;
+                    let enum_type = EnumType {
                         name: name.lexeme.clone(),
-                        arity,
-                        fun: Rc::new(fun_impl),
+                        variants: variants.iter().map(|v| v.lexeme.clone()).collect(),
                     };
 
-                    
+                    self.environment.borrow_mut()
+                        .define_constant(name.lexeme.clone(), LiteralValue::Enum(Rc::new(enum_type)));
+                },
+                Stmt::ForIn { target, iterable, body } => {
+                    let iterable = iterable.evaluate(self.environment.clone())?;
+                    let mut items = Iteration::new(&iterable, target.line(), &self.environment)?;
+                    while let Some(item) = items.next()? {
+                        if !keep_looping(self.interpret_with(target, item, body))? {
+                            break;
+                        }
+                    }
+                },
+                Stmt::Match { keyword, subject, arms } => {
+                    let subject = subject.evaluate(self.environment.clone())?;
+                    self.interpret_match(keyword, &subject, arms)?;
+                },
+                Stmt::Yield { keyword, value: _ } => {
+                    // Generators run their own yielding statements, so one
+                    // only gets here from outside a generator.
+                    return Err(RuntimeError::error(keyword.line_num, "'yield' outside a generator".to_string()));
+                },
+                Stmt::Function { name, params, body, generator, source } => {
+                    self.check_redeclaration(name)?;
+                    let callable = LoxFunction::declared(name, params, body, *generator, source, self.environment.clone());
+
                     self.environment.borrow_mut().define(name.lexeme.clone(), callable);
                 }
             };
@@ -0,0 +1,208 @@
+use crate::error::{LoxError, RuntimeError};
+use crate::expr::LiteralValue;
+use crate::value::{self, FromLox, IntoLoxResult, LoxValue};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{Map, Number, Value};
+
+// Structured data crosses between Rust and Lox as JSON: objects become
+// maps, arrays become lists, and back. Anything serde can handle goes
+// through `serde_json::Value` on the way.
+
+// Deeper than this is almost certainly a list or map that contains itself.
+const MAX_DEPTH: usize = 128;
+
+pub fn to_lox<T: Serialize + ?Sized>(value: &T) -> Result<LoxValue, LoxError> {
+    match serde_json::to_value(value) {
+        Ok(json) => from_json(json).map(LoxValue).map_err(LoxError::Host),
+        Err(e) => Err(LoxError::Host(e.to_string())),
+    }
+}
+
+pub fn from_lox<T: DeserializeOwned>(value: LoxValue) -> Result<T, LoxError> {
+    let json = Value::from_lox(value).map_err(LoxError::Host)?;
+    serde_json::from_value(json).map_err(|e| LoxError::Host(e.to_string()))
+}
+
+// Wraps a serde type so it can be used wherever a conversion is expected:
+// `let Serde(config): Serde<Config> = lox.eval(...)?`, or as the argument
+// or return type of a registered function.
+pub struct Serde<T>(pub T);
+
+impl<T: DeserializeOwned> FromLox for Serde<T> {
+    fn from_lox(value: LoxValue) -> Result<Self, String> {
+        from_lox(value).map(Serde).map_err(|e| e.to_string())
+    }
+}
+
+impl<T: Serialize> IntoLoxResult for Serde<T> {
+    fn into_lox_result(self) -> Result<LoxValue, RuntimeError> {
+        to_lox(&self.0).map_err(|e| RuntimeError::error(0, e.to_string()))
+    }
+}
+
+impl IntoLoxResult for Value {
+    fn into_lox_result(self) -> Result<LoxValue, RuntimeError> {
+        from_json(self).map(LoxValue).map_err(|e| RuntimeError::error(0, e))
+    }
+}
+
+fn from_json(value: Value) -> Result<LiteralValue, String> {
+    match value {
+        Value::Null => Ok(LiteralValue::Nil),
+        Value::Bool(b) => Ok(LiteralValue::from_bool(b)),
+        Value::Number(n) => Ok(LiteralValue::Number(from_number(&n)?)),
+        Value::String(s) => Ok(LiteralValue::StringValue(s)),
+        Value::Array(items) => {
+            let items: Result<Vec<LiteralValue>, String> = items.into_iter().map(from_json).collect();
+            Ok(LiteralValue::list(items?))
+        },
+        Value::Object(entries) => {
+            let mut converted = vec![];
+            for (key, value) in entries {
+                converted.push((key, from_json(value)?));
+            }
+            Ok(LiteralValue::map(converted))
+        },
+    }
+}
+
+// Numbers convert the way the host's own numbers do.
+fn from_number(n: &Number) -> Result<f32, String> {
+    match (n.as_i64(), n.as_u64(), n.as_f64()) {
+        (Some(i), _, _) => value::whole_number(i as i128),
+        (_, Some(u), _) => value::whole_number(u as i128),
+        (_, _, Some(x)) => value::number(x),
+        _ => Err(format!("cannot convert {} to a number", n)),
+    }
+}
+
+impl FromLox for Value {
+    fn from_lox(value: LoxValue) -> Result<Self, String> {
+        to_json(&value.0, 0)
+    }
+}
+
+// Whole numbers become JSON integers so they deserialize into integer
+// fields. Enum values become their variant name, which is how serde
+// represents unit variants.
+fn to_json(value: &LiteralValue, depth: usize) -> Result<Value, String> {
+    if depth > MAX_DEPTH {
+        return Err("value is nested too deeply to convert, does it contain itself?".to_string());
+    }
+
+    match value {
+        LiteralValue::Nil => Ok(Value::Null),
+        LiteralValue::True => Ok(Value::Bool(true)),
+        LiteralValue::False => Ok(Value::Bool(false)),
+        LiteralValue::Number(x) if x.fract() == 0.0 && x.abs() < i64::MAX as f32 => Ok(Value::Number((*x as i64).into())),
+        LiteralValue::Number(x) => match Number::from_f64(*x as f64) {
+            Some(n) => Ok(Value::Number(n)),
+            None => Err(format!("cannot convert {} to JSON", x)),
+        },
+        LiteralValue::StringValue(s) => Ok(Value::String(s.clone())),
+        LiteralValue::List(items) => {
+            let items = items.borrow();
+            let items: Result<Vec<Value>, String> = items.iter().map(|item| to_json(item, depth + 1)).collect();
+            Ok(Value::Array(items?))
+        },
+        LiteralValue::Map(entries) => {
+            let mut object = Map::new();
+            for (key, value) in entries.borrow().iter() {
+                object.insert(key.clone(), to_json(value, depth + 1)?);
+            }
+            Ok(Value::Object(object))
+        },
+        LiteralValue::EnumValue { enum_type, index } => Ok(Value::String(enum_type.variants[*index].clone())),
+        other => Err(format!("cannot convert {} to JSON", other.to_type())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_lox, to_lox, Serde};
+    use crate::lox::Lox;
+    use crate::value::LoxValue;
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum Level {
+        Low,
+        High,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Job {
+        name: String,
+        retries: u32,
+        tags: Vec<String>,
+        level: Level,
+        timeout: Option<f64>,
+    }
+
+    fn job() -> Job {
+        Job {
+            name: "backup".to_string(),
+            retries: 3,
+            tags: vec!["nightly".to_string()],
+            level: Level::Low,
+            timeout: None,
+        }
+    }
+
+    #[test]
+    fn structs_round_trip() {
+        let value = to_lox(&job()).unwrap();
+        assert_eq!(value.to_string(), "{name: \"backup\", retries: 3, tags: [\"nightly\"], level: \"Low\", timeout: nil}");
+        assert_eq!(from_lox::<Job>(value).unwrap(), job());
+    }
+
+    #[test]
+    fn scripts_read_and_build_structured_data() {
+        let mut lox = Lox::new();
+        lox.set("job", to_lox(&job()).unwrap()).unwrap();
+        lox.eval::<()>("enum Level { Low, High } job.retries += 1; job.level = Level.High; job.tags[0] = \"weekly\";").unwrap();
+
+        let Serde(updated): Serde<Job> = lox.get("job").unwrap();
+        assert_eq!(updated.retries, 4);
+        assert_eq!(updated.level, Level::High);
+        assert_eq!(updated.tags, vec!["weekly".to_string()]);
+
+        let built: serde_json::Value = lox.eval("var built = {ok: true, \"items\": [1, 2.5], none: nil}; built;").unwrap();
+        assert_eq!(built, json!({"ok": true, "items": [1, 2.5], "none": null}));
+    }
+
+    #[test]
+    fn registered_functions_take_and_return_serde_types() {
+        let mut lox = Lox::new();
+        lox.register_fn("describe", |Serde(job): Serde<Job>| format!("{} x{}", job.name, job.retries));
+        lox.register_fn("load", || Serde(job()));
+        assert_eq!(lox.eval::<String>("describe(load());").unwrap(), "backup x3");
+
+        let error = lox.eval::<()>("describe({name: \"x\"});").unwrap_err().to_string();
+        assert_eq!(error, "Line 1: argument 1 of describe: missing field `retries`");
+    }
+
+    #[test]
+    fn numbers_convert_exactly_or_not_at_all() {
+        assert_eq!(to_lox(&16_777_216u64).unwrap().to_string(), "16777216");
+        assert_eq!(to_lox(&-3i32).unwrap().to_string(), "-3");
+        assert_eq!(to_lox(&2.5f64).unwrap().to_string(), "2.5");
+
+        let error = to_lox(&json!({"id": 16_777_217u64})).unwrap_err().to_string();
+        assert_eq!(error, "cannot convert 16777217 to a number exactly");
+        assert!(to_lox(&u64::MAX).is_err());
+        assert!(to_lox(&1e300f64).is_err());
+    }
+
+    #[test]
+    fn values_without_data_are_rejected() {
+        let mut lox = Lox::new();
+        let error = lox.eval::<serde_json::Value>("[clock];").unwrap_err().to_string();
+        assert_eq!(error, "cannot convert Callable to JSON");
+
+        let cyclic: LoxValue = lox.eval("var m = {}; m.m = m; m;").unwrap();
+        assert!(from_lox::<serde_json::Value>(cyclic).is_err());
+    }
+}
@@ -0,0 +1,37 @@
+mod scanner;
+mod expr;
+mod parser;
+mod interpreter;
+mod stmt;
+mod environment;
+mod error;
+mod module;
+mod resolver;
+mod generator;
+mod tasks;
+mod value;
+mod native;
+mod host;
+mod json;
+mod output;
+mod limits;
+mod sandbox;
+mod heap;
+mod snapshot;
+mod lox;
+
+#[cfg(test)]
+mod tests;
+
+use crate::scanner::*;
+
+pub use crate::error::{LoxError, RuntimeError};
+pub use crate::heap::GcStats;
+pub use crate::host::HostObject;
+pub use crate::limits::CancelHandle;
+pub use crate::json::{from_lox, to_lox, Serde};
+pub use crate::lox::Lox;
+pub use crate::native::{CallContext, HostFn};
+pub use crate::output::OutputBuffer;
+pub use crate::sandbox::{Capabilities, Capability};
+pub use crate::value::{FromLox, IntoLox, IntoLoxResult, LoxValue};
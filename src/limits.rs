@@ -0,0 +1,155 @@
+use crate::environment::Environment;
+use crate::error::RuntimeError;
+use crate::expr::LiteralValue;
+use crate::heap;
+use std::cell::{Cell, RefCell};
+use std::mem;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+// Looking at the clock on every step would cost more than the step itself.
+const DEADLINE_INTERVAL: u64 = 1024;
+
+// Measuring walks everything reachable, so once the estimate is over the
+// limit it is only done again after another 1/MEASURE_FRACTION of the
+// limit has been allocated.
+const MEASURE_FRACTION: usize = 16;
+
+// How much a run may do before it is stopped: a number of steps (every
+// statement and every expression is one), a point in time, a number of
+// bytes, and a handle another thread can use to stop it. None of them are
+// set by default.
+//
+// Memory is an estimate of what the engine's strings, lists, map entries
+// and variables take up. Values don't say when they are dropped, so `used`
+// only grows as they are allocated; once it would cross the limit, what is
+// still reachable is measured again and only that counts. Between two
+// measurements a run can go over by up to a sixteenth of the limit.
+#[derive(Default)]
+pub struct Limits {
+    pub fuel: Cell<Option<u64>>,
+    pub deadline: Cell<Option<Instant>>,
+    pub memory: Cell<Option<usize>>,
+    pub cancel: CancelHandle,
+    // What was in use when last measured, plus what was allocated since.
+    pub used: Cell<usize>,
+    // What was in use when last measured.
+    measured: Cell<usize>,
+    steps: Cell<u64>,
+}
+
+impl Limits {
+    // Takes one step, failing if any limit has been reached.
+    pub fn step(&self) -> Result<(), RuntimeError> {
+        if let Some(fuel) = self.fuel.get() {
+            if fuel == 0 {
+                return Err(exceeded("out of fuel"));
+            }
+            self.fuel.set(Some(fuel - 1));
+        }
+
+        if self.cancel.cancelled.load(Ordering::Relaxed) {
+            return Err(exceeded("cancelled"));
+        }
+
+        let steps = self.steps.get().wrapping_add(1);
+        self.steps.set(steps);
+        match self.deadline.get() {
+            Some(deadline) if steps.is_multiple_of(DEADLINE_INTERVAL) && Instant::now() >= deadline => {
+                Err(exceeded("deadline passed"))
+            },
+            _ => Ok(()),
+        }
+    }
+
+    // Accounts for `bytes` more, before they are allocated. `measure` works
+    // out what is in use right now, for when the estimate is over the limit.
+    pub fn allocate(&self, bytes: usize, measure: impl FnOnce() -> usize) -> Result<(), RuntimeError> {
+        let memory = match self.memory.get() {
+            Some(memory) => memory,
+            None => {
+                self.used.set(self.used.get().saturating_add(bytes));
+                return Ok(());
+            },
+        };
+
+        let mut used = self.used.get().saturating_add(bytes);
+        if used > memory && used - self.measured.get() >= memory / MEASURE_FRACTION {
+            used = measure().saturating_add(bytes);
+            if used > memory {
+                return Err(RuntimeError::Limit {
+                    message: format!("memory limit exceeded ({} bytes)", memory),
+                });
+            }
+        }
+        self.used.set(used);
+        Ok(())
+    }
+
+    // A cancellation stops one run; the next one starts afresh.
+    pub fn end_run(&self) {
+        self.cancel.cancelled.store(false, Ordering::Relaxed);
+    }
+}
+
+// Stops whatever the engine it came from is running, from any thread.
+#[derive(Clone, Default)]
+pub struct CancelHandle {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelHandle {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+fn exceeded(reason: &str) -> RuntimeError {
+    RuntimeError::Limit {
+        message: format!("execution limit exceeded ({})", reason),
+    }
+}
+
+// Takes one step for code running in `environment`. Code without a context
+// isn't running under any limits.
+pub fn step(environment: &Rc<RefCell<Environment>>) -> Result<(), RuntimeError> {
+    match environment.borrow().context() {
+        Some(context) => context.limits.step(),
+        None => Ok(()),
+    }
+}
+
+// Accounts for `bytes` allocated by code running in `environment`.
+pub fn allocate(environment: &Rc<RefCell<Environment>>, bytes: usize) -> Result<(), RuntimeError> {
+    if bytes == 0 {
+        return Ok(());
+    }
+    let context = environment.borrow().context();
+    match context {
+        Some(context) => context.limits.allocate(bytes, || measure(&context.limits)),
+        None => Ok(()),
+    }
+}
+
+// Works out what the engine `limits` belongs to has in use, once garbage
+// cycles are gone, and makes that the new estimate.
+pub fn measure(limits: &Rc<Limits>) -> usize {
+    heap::collect();
+    let used = heap::used_by(limits);
+    limits.used.set(used);
+    limits.measured.set(used);
+    used
+}
+
+// Estimated sizes of what scripts allocate. Values are stored inline, so
+// a list or a variable costs a slot per value on top of whatever the value
+// allocated when it was made; a string costs its length.
+pub fn list_size(len: usize) -> usize {
+    len * mem::size_of::<LiteralValue>()
+}
+
+pub fn entry_size(key: &str) -> usize {
+    mem::size_of::<(String, LiteralValue)>() + key.len()
+}
@@ -0,0 +1,426 @@
+use crate::error::{LoxError, RuntimeError};
+use crate::expr::LiteralValue;
+use crate::heap::{self, GcStats};
+use crate::interpreter::Interpreter;
+use crate::limits::{self, CancelHandle, Limits};
+use crate::module::Context;
+use crate::native::{native_fn, native_fn_requiring, HostFn};
+use crate::parser::Parser;
+use crate::resolver::Resolver;
+use crate::sandbox::{Capabilities, Capability};
+use crate::snapshot;
+use crate::scanner::Scanner;
+use crate::stmt::Stmt;
+use crate::value::{FromLox, IntoLox, LoxValue};
+use crate::output::Output;
+use std::cell::RefCell;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::Instant;
+
+// An interpreter for a host program to embed. Globals persist from one
+// `eval` or `run_file` to the next, the way they do between REPL lines.
+pub struct Lox {
+    interpreter: Interpreter,
+}
+
+impl Default for Lox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Lox {
+    pub fn new() -> Self {
+        Self {
+            interpreter: Interpreter::new(),
+        }
+    }
+
+    // Runs `source` and converts the value of its trailing expression
+    // statement, or nil if it doesn't end with one. Spawned tasks are run
+    // to completion before it returns.
+    pub fn eval<T: FromLox>(&mut self, source: &str) -> Result<T, LoxError> {
+        let value = self.run(source)?;
+        T::from_lox(LoxValue(value)).map_err(LoxError::Host)
+    }
+
+    // Runs a script file. Its imports resolve relative to the file.
+    pub fn run_file(&mut self, path: impl AsRef<Path>) -> Result<(), LoxError> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path).map_err(|e| LoxError::Io(e.to_string()))?;
+        let file = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        *self.context().file.borrow_mut() = Some(file);
+
+        self.run(&source)?;
+        Ok(())
+    }
+
+    // Where `print` writes. Defaults to the process's stdout.
+    pub fn set_stdout(&mut self, sink: impl Write + 'static) {
+        self.output().borrow_mut().stdout = Box::new(sink);
+    }
+
+    // Where `report` writes. Defaults to the process's stderr.
+    pub fn set_stderr(&mut self, sink: impl Write + 'static) {
+        self.output().borrow_mut().stderr = Box::new(sink);
+    }
+
+    // Writes an error the way the command line shows it.
+    pub fn report(&self, error: &LoxError) {
+        let output = self.output();
+        let mut output = output.borrow_mut();
+        // Nowhere left to report a failing error sink to.
+        let _ = writeln!(output.stderr, "ERROR: {}", error);
+    }
+
+    // How many more steps scripts may take, where every statement and
+    // expression is one. Unlimited by default; it is used up across runs
+    // until set again.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.limits().fuel.set(fuel);
+    }
+
+    pub fn fuel(&self) -> Option<u64> {
+        self.limits().fuel.get()
+    }
+
+    // A point in time after which scripts are stopped. None by default.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.limits().deadline.set(deadline);
+    }
+
+    // Roughly how many bytes the strings, lists, maps and variables scripts
+    // keep may take up. What a script lets go of no longer counts, so a
+    // loop that builds and drops values runs as long as it likes. Unlimited
+    // by default.
+    pub fn set_memory_limit(&mut self, bytes: Option<usize>) {
+        self.limits().memory.set(bytes);
+    }
+
+    // What the engine's values take up right now, by the same estimate.
+    pub fn memory_used(&self) -> usize {
+        limits::measure(&self.limits())
+    }
+
+    // What scripts may touch: files, environment variables, the clock and
+    // the process. Nothing by default.
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        *self.context().capabilities.borrow_mut() = capabilities;
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        self.context().capabilities.borrow().clone()
+    }
+
+    // Frees values that are only kept alive by cycles, such as a closure
+    // stored in the scope it closes over, and returns how many objects it
+    // freed. Scripts also collect on their own as they allocate.
+    pub fn gc(&mut self) -> usize {
+        heap::collect()
+    }
+
+    // Shared by every engine on this thread, since their values can mix.
+    pub fn gc_stats(&self) -> GcStats {
+        heap::stats()
+    }
+
+    // Saves the globals to `path`, to be restored into another engine later.
+    // Fails on values that can't be saved, such as host objects, natives and
+    // functions that close over local variables.
+    pub fn save_snapshot(&self, path: impl AsRef<Path>) -> Result<(), LoxError> {
+        snapshot::save(&self.interpreter.globals(), path.as_ref())
+    }
+
+    // Defines the globals saved in `path`, replacing any of the same name.
+    pub fn restore_snapshot(&mut self, path: impl AsRef<Path>) -> Result<(), LoxError> {
+        snapshot::restore(&self.interpreter.globals(), path.as_ref())
+    }
+
+    // A handle that stops the current run from another thread. A cancel
+    // that comes while nothing is running stops the next run instead.
+    pub fn cancel_handle(&self) -> CancelHandle {
+        self.limits().cancel.clone()
+    }
+
+    fn context(&self) -> Rc<Context> {
+        let context = self.interpreter.globals().borrow().context();
+        context.expect("the engine's globals always have a context")
+    }
+
+    fn output(&self) -> Rc<RefCell<Output>> {
+        self.context().output.clone()
+    }
+
+    fn limits(&self) -> Rc<Limits> {
+        self.context().limits.clone()
+    }
+
+    pub fn get<T: FromLox>(&self, name: &str) -> Result<T, LoxError> {
+        let value = self.interpreter.globals().borrow().get(name);
+        match value {
+            Some(value) => T::from_lox(LoxValue(value)).map_err(|e| LoxError::Host(format!("global '{}': {}", name, e))),
+            None => Err(LoxError::Host(format!("undefined variable '{}'", name))),
+        }
+    }
+
+    // Defines or replaces a global. Constants can't be replaced.
+    pub fn set(&mut self, name: &str, value: impl IntoLox) -> Result<(), LoxError> {
+        let globals = self.interpreter.globals();
+        if globals.borrow().declares_constant(name) {
+            return Err(LoxError::Host(format!("cannot assign to constant '{}'", name)));
+        }
+        let value = value.into_lox().map_err(|e| LoxError::Host(format!("global '{}': {}", name, e)))?;
+        globals.borrow_mut().define(name.to_string(), value.0);
+        Ok(())
+    }
+
+    // Makes a Rust closure callable from Lox as a global function. Its
+    // parameter types set the arity and convert the arguments; returning
+    // an `Err` raises a runtime error at the call.
+    pub fn register_fn<Args, F: HostFn<Args>>(&mut self, name: &str, fun: F) {
+        self.interpreter.globals()
+            .borrow_mut()
+            .define(name.to_string(), native_fn(name, fun));
+    }
+
+    // Registers a function that reaches outside the interpreter, such as
+    // one that reads files. Calls fail unless the capabilities set with
+    // `set_capabilities` allow everything in `needs`.
+    pub fn register_fn_requiring<Args, F: HostFn<Args>>(&mut self, name: &str, needs: &[Capability], fun: F) {
+        self.interpreter.globals()
+            .borrow_mut()
+            .define(name.to_string(), native_fn_requiring(name, needs, fun));
+    }
+
+    fn run(&mut self, source: &str) -> Result<LiteralValue, LoxError> {
+        let tokens = Scanner::new(source).scan_tokens().map_err(LoxError::Compile)?;
+        let stmts = Parser::new(tokens, source).parse().map_err(LoxError::Compile)?;
+        Resolver::new().resolve(&stmts).map_err(LoxError::Compile)?;
+
+        let result = self.interpret(&stmts).and_then(|value| {
+            self.interpreter.run_tasks()?;
+            Ok(value)
+        });
+        self.limits().end_run();
+        Ok(result?)
+    }
+
+    fn interpret(&mut self, stmts: &[Stmt]) -> Result<LiteralValue, RuntimeError> {
+        let (last, init) = match stmts.split_last() {
+            Some(split) => split,
+            None => return Ok(LiteralValue::Nil),
+        };
+
+        self.interpreter.interpret(init.iter().collect())?;
+        match last {
+            Stmt::Expression { expression } => expression.evaluate(self.interpreter.globals()),
+            stmt => {
+                self.interpreter.interpret(vec![stmt])?;
+                Ok(LiteralValue::Nil)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Lox;
+    use crate::error::LoxError;
+    use crate::native::CallContext;
+    use crate::output::OutputBuffer;
+    use crate::sandbox::Capabilities;
+    use crate::value::{IntoLox, LoxValue};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn evaluates_to_the_trailing_expression() {
+        let mut lox = Lox::new();
+        assert_eq!(lox.eval::<f64>("var a = 2; a * 3;").unwrap(), 6.0);
+        assert_eq!(lox.eval::<String>("\"a\" + \"b\";").unwrap(), "ab");
+        assert_eq!(lox.eval::<LoxValue>("var b = 1;").unwrap(), LoxValue::nil());
+        assert_eq!(lox.eval::<Vec<f64>>("[a, a + 1];").unwrap(), vec![2.0, 3.0]);
+    }
+
+    #[test]
+    fn globals_persist_between_runs() {
+        let mut lox = Lox::new();
+        lox.set("limit", 10.0).unwrap();
+        lox.eval::<()>("var doubled = limit * 2;").unwrap();
+        assert_eq!(lox.get::<f64>("doubled").unwrap(), 20.0);
+        assert_eq!(lox.get::<Option<f64>>("doubled").unwrap(), Some(20.0));
+
+        lox.eval::<()>("const NAME = \"cii\";").unwrap();
+        assert_eq!(lox.set("NAME", "other").unwrap_err().to_string(), "cannot assign to constant 'NAME'");
+        assert_eq!(lox.get::<f64>("missing").unwrap_err().to_string(), "undefined variable 'missing'");
+        assert_eq!(lox.get::<bool>("NAME").unwrap_err().to_string(), "global 'NAME': expected Boolean, got String");
+    }
+
+    #[test]
+    fn reports_errors() {
+        let mut lox = Lox::new();
+        assert_eq!(lox.eval::<()>("var = 1;").unwrap_err().to_string(), "Expected variable name");
+        assert_eq!(lox.eval::<()>("throw 1;").unwrap_err().to_string(), "Line 1: uncaught exception 1");
+        assert_eq!(lox.eval::<f64>("\"x\";").unwrap_err().to_string(), "expected Number, got String");
+        assert!(lox.run_file("./src/tests/cases/missing.lox").is_err());
+    }
+
+    #[test]
+    fn registered_functions() {
+        let mut lox = Lox::new();
+        lox.register_fn("add", |a: f64, b: f64| a + b);
+        lox.register_fn("greet", |name: String, greeting: Option<String>| {
+            format!("{}, {}", greeting.unwrap_or("Hello".to_string()), name)
+        });
+        lox.register_fn("total", |items: Vec<f64>| items.iter().sum::<f64>());
+        lox.register_fn("sqrt", |x: f64| {
+            if x < 0.0 {
+                return Err(format!("cannot take the square root of {}", x));
+            }
+            Ok(x.sqrt())
+        });
+        lox.register_fn("twice", |context: &CallContext, f: LoxValue| -> Result<f64, LoxError> {
+            let once: f64 = context.call(&f, vec![1.0.into_lox().map_err(LoxError::Host)?])?;
+            let scale: f64 = context.get("scale")?;
+            Ok(once * 2.0 * scale)
+        });
+
+        assert_eq!(lox.eval::<f64>("add(1, 2);").unwrap(), 3.0);
+        assert_eq!(lox.eval::<String>("greet(\"Ada\");").unwrap(), "Hello, Ada");
+        assert_eq!(lox.eval::<String>("greet(\"Ada\", \"Hi\");").unwrap(), "Hi, Ada");
+        assert_eq!(lox.eval::<f64>("total([1, 2, 3]);").unwrap(), 6.0);
+        assert_eq!(lox.eval::<f64>("var scale = 3; twice(fun (x) { x + 1; });").unwrap(), 12.0);
+
+        let mut error = |source: &str| lox.eval::<()>(source).unwrap_err().to_string();
+        assert_eq!(error("\n sqrt(-4);"), "Line 2: cannot take the square root of -4");
+        assert_eq!(error("add(1, \"2\");"), "Line 1: argument 2 of add: expected Number, got String");
+        assert_eq!(error("add(1);"), "Line 1: Callable add expected 2 arguments but got 1");
+        assert_eq!(error("greet();"), "Line 1: Callable greet expected 1 to 2 arguments but got 0");
+        assert_eq!(error("sqrt();"), "Line 1: Callable sqrt expected 1 argument but got 0");
+        assert_eq!(error("fun gather(first, ...rest) {} gather();"), "Line 1: Callable gather expected at least 1 argument but got 0");
+        assert_eq!(error("add(1, b: 2);"), "Line 1: Callable add does not take named arguments");
+    }
+
+    #[test]
+    fn output_goes_to_the_configured_sinks() {
+        let stdout = OutputBuffer::new();
+        let stderr = OutputBuffer::new();
+        let mut lox = Lox::new();
+        lox.set_stdout(stdout.clone());
+        lox.set_stderr(stderr.clone());
+
+        lox.eval::<()>("print 1; fun f() { print \"in f\"; } f();").unwrap();
+        assert_eq!(stdout.take(), "1\n\"in f\"\n");
+
+        let error = lox.eval::<()>("print 2; throw \"bad\";").unwrap_err();
+        lox.report(&error);
+        assert_eq!(stdout.contents(), "2\n");
+        assert_eq!(stderr.contents(), "ERROR: Line 1: uncaught exception \"bad\"\n");
+    }
+
+    #[test]
+    fn fuel_runs_out() {
+        let mut lox = Lox::new();
+        lox.set_fuel(Some(1000));
+        let error = lox.eval::<()>("var n = 0; while (true) { try { n += 1; } catch (e) {} }").unwrap_err();
+        assert_eq!(error.to_string(), "execution limit exceeded (out of fuel)");
+        assert_eq!(lox.fuel(), Some(0));
+
+        lox.set_fuel(Some(1000));
+        assert!(lox.get::<f64>("n").unwrap() > 10.0);
+        assert_eq!(lox.eval::<f64>("1 + 2;").unwrap(), 3.0);
+        assert!(lox.fuel().unwrap() < 1000);
+    }
+
+    #[test]
+    fn deadlines_and_cancellation() {
+        let mut lox = Lox::new();
+        lox.set_deadline(Some(Instant::now() + Duration::from_millis(20)));
+        let error = lox.eval::<()>("fun spin() { while (true) {} } spin();").unwrap_err();
+        assert_eq!(error.to_string(), "execution limit exceeded (deadline passed)");
+        lox.set_deadline(None);
+
+        lox.set_stdout(OutputBuffer::new());
+        let cancel = lox.cancel_handle();
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            cancel.cancel();
+        });
+        let error = lox.eval::<()>("for (var i = 0; true; i++) { print i; }").unwrap_err();
+        canceller.join().unwrap();
+        assert_eq!(error.to_string(), "execution limit exceeded (cancelled)");
+        assert_eq!(lox.eval::<f64>("1;").unwrap(), 1.0);
+    }
+
+    #[test]
+    fn memory_runs_out() {
+        let mut lox = Lox::new();
+        lox.set_memory_limit(Some(1 << 20));
+        let error = lox.eval::<()>("var s = \"ab\"; while (true) { try { s = s + s; } catch (e) {} }").unwrap_err();
+        assert_eq!(error.to_string(), "memory limit exceeded (1048576 bytes)");
+        assert!(lox.get::<String>("s").unwrap().len() <= 1 << 20);
+
+        let mut error = |source: &str| lox.eval::<()>(source).unwrap_err().to_string();
+        assert_eq!(error("var l = []; while (true) { l = [l, l, l, l]; }"), "memory limit exceeded (1048576 bytes)");
+        assert_eq!(error("var m = {}; var k = \"k\"; while (true) { k += \"k\"; m[k] = 1; }"), "memory limit exceeded (1048576 bytes)");
+
+        assert_eq!(lox.eval::<f64>("s = nil; l = nil; m = nil; k = nil; var small = [1, 2, 3]; small[0];").unwrap(), 1.0);
+        assert!(lox.memory_used() > 0 && lox.memory_used() < 4096);
+    }
+
+    #[test]
+    fn memory_that_is_let_go_of_is_not_counted() {
+        let mut lox = Lox::new();
+        lox.set_memory_limit(Some(64 * 1024));
+        let source = "
+            var kept = \"\";
+            for (var i = 0; i < 2000; i++) {
+                var s = \"ab\";
+                for (var j = 0; j < 8; j++) s = s + s;
+                var l = [s, s, {key: s}];
+                l[0] = l;
+                kept = s;
+            }
+            kept;
+        ";
+        assert_eq!(lox.eval::<String>(source).unwrap().len(), 512);
+        assert!(lox.memory_used() < 4096);
+    }
+
+    #[test]
+    fn memory_near_the_limit_is_not_measured_every_time() {
+        let mut lox = Lox::new();
+        lox.set("big", vec![0.0; 1000]).unwrap();
+        let map: std::collections::BTreeMap<String, f64> = (0..1000).map(|i| (format!("k{}", i), 0.0)).collect();
+        lox.set("map", crate::json::to_lox(&map).unwrap()).unwrap();
+        let used = lox.memory_used();
+        lox.set_memory_limit(Some(used + used / 4));
+
+        let collections = lox.gc_stats().collections;
+        lox.eval::<()>("for (var i = 0; i < 2000; i++) { var s = \"abcdefgh\" + \"ijklmnop\"; }").unwrap();
+        assert!(lox.gc_stats().collections - collections < 100);
+
+        // Copies made for a rest pattern or a loop over a map count too.
+        let mut error = |source: &str| lox.eval::<()>(source).unwrap_err().to_string();
+        let exceeded = format!("memory limit exceeded ({} bytes)", used + used / 4);
+        assert_eq!(error("var [first, ...others] = big;"), exceeded);
+        assert_eq!(error("for (var pair in map) {}"), exceeded);
+    }
+
+    #[test]
+    fn runs_files_with_relative_imports() {
+        let mut lox = Lox::new();
+        lox.set_capabilities(Capabilities {
+            read: vec!["./src/tests/cases/modules".into()],
+            ..Capabilities::none()
+        });
+        let output = OutputBuffer::new();
+        lox.set_stdout(output.clone());
+        lox.run_file("./src/tests/cases/modules/lib/geometry.lox").unwrap();
+        assert_eq!(lox.eval::<f64>("area(3);").unwrap(), 9.0);
+        assert_eq!(output.contents(), "\"loading math\"\n");
+    }
+}
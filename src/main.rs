@@ -1,49 +1,19 @@
-mod scanner;
-mod expr;
-mod parser;
-mod interpreter;
-mod stmt;
-mod environment;
-
-#[cfg(test)]
-mod tests;
-
-use parser::Parser;
-
-use crate::scanner::*;
-use crate::interpreter::Interpreter;
+use cii::{Capabilities, Lox};
 
 use std::env;
 use std::io::Write;
-use std::fs;
 use std::io;
 use std::process::exit;
 
-fn run_file(path: &str) -> Result<(), String> {
-    let mut interpreter = Interpreter::new();
-    match fs::read_to_string(path) {
-        Ok(contents) => {
-            return run(&mut interpreter, &contents);
-        },
-        Err(e) => {
-            return Err(e.to_string());
-        }
-    }
-}
-
-fn run(interpreter: &mut Interpreter, contents: &str) -> Result<(), String> {
-    let mut scanner = Scanner::new(contents);
-    let tokens = scanner.scan_tokens()?;
-
-    let mut parser = Parser::new(tokens);
-    let stmts = parser.parse()?;
-    interpreter.interpret(stmts.iter().collect())?;
-
-    Ok(())
+// The command line runs the user's own scripts, so they may touch anything.
+fn new_lox() -> Lox {
+    let mut lox = Lox::new();
+    lox.set_capabilities(Capabilities::all());
+    lox
 }
 
 fn run_prompt() -> Result<(), String>{
-    let mut interpreter: Interpreter = Interpreter::new();
+    let mut lox = new_lox();
     loop {
         print!("> ");
         match io::stdout().flush() { // need to flush to stdout, else it doesn't print to the terminal
@@ -65,9 +35,15 @@ fn run_prompt() -> Result<(), String>{
             }
         }
         print!("ECHO: {}", &buffer);
-        match run(&mut interpreter, &buffer) {
+        // `:save file` and `:load file` keep a session's globals for later.
+        let result = match buffer.trim().split_once(' ') {
+            Some((":save", path)) => lox.save_snapshot(path.trim()),
+            Some((":load", path)) => lox.restore_snapshot(path.trim()),
+            _ => lox.eval::<()>(&buffer),
+        };
+        match result {
             Ok(_) => {},
-            Err(e) => eprintln!("ERROR: {}", e),
+            Err(e) => lox.report(&e),
         }
     }
 }
@@ -79,7 +55,7 @@ fn main() {
         println!("Usage: jlox [script]");
         exit(64);
     }else if args.len() == 2 {
-        match run_file(&args[1]) {
+        match new_lox().run_file(&args[1]) {
             Ok(_) => exit(0),
             Err(e) => println!("ERROR: {}", e),
         }
@@ -0,0 +1,152 @@
+use crate::error::RuntimeError;
+use crate::expr::LiteralValue;
+use crate::interpreter::Interpreter;
+use crate::limits::Limits;
+use crate::output::Output;
+use crate::parser::Parser;
+use crate::resolver::Resolver;
+use crate::sandbox::Capabilities;
+use crate::scanner::Scanner;
+use crate::tasks::Scheduler;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+// Every module loaded during one run, keyed by canonical path, plus the
+// chain of modules currently being loaded so cycles can be reported.
+#[derive(Default)]
+pub struct ModuleCache {
+    loaded: HashMap<PathBuf, LiteralValue>,
+    loading: Vec<PathBuf>,
+}
+
+// Stored in the global environment of a script or module. `file` is where
+// its code came from, so imports resolve relative to it; an engine running
+// several files in turn points it at each one. The module cache and the
+// task scheduler are shared by all modules of the run, and so are where
+// their output goes, the limits they run under and what they may touch.
+pub struct Context {
+    pub file: RefCell<Option<PathBuf>>,
+    pub modules: Rc<RefCell<ModuleCache>>,
+    pub scheduler: Rc<RefCell<Scheduler>>,
+    pub output: Rc<RefCell<Output>>,
+    pub limits: Rc<Limits>,
+    pub capabilities: Rc<RefCell<Capabilities>>,
+}
+
+impl Context {
+    pub fn new(file: Option<PathBuf>) -> Self {
+        Self {
+            file: RefCell::new(file),
+            modules: Rc::new(RefCell::new(ModuleCache::default())),
+            scheduler: Rc::new(RefCell::new(Scheduler::default())),
+            output: Rc::new(RefCell::new(Output::default())),
+            limits: Rc::new(Limits::default()),
+            capabilities: Rc::new(RefCell::new(Capabilities::none())),
+        }
+    }
+
+    fn for_module(&self, file: PathBuf) -> Self {
+        Self {
+            file: RefCell::new(Some(file)),
+            modules: self.modules.clone(),
+            scheduler: self.scheduler.clone(),
+            output: self.output.clone(),
+            limits: self.limits.clone(),
+            capabilities: self.capabilities.clone(),
+        }
+    }
+
+    fn resolve(&self, path: &str) -> PathBuf {
+        match self.file.borrow().as_ref().and_then(|file| file.parent()) {
+            Some(dir) => dir.join(path),
+            None => PathBuf::from(path),
+        }
+    }
+}
+
+// Loads the module at `path` (relative to the importing file) and returns
+// its namespace. A module runs at most once per run; later imports get the
+// cached namespace.
+//
+// The capability check comes first, so a script that may not read a path
+// can't tell from the error whether something is there.
+pub fn import(context: &Context, path: &str, line: usize) -> Result<LiteralValue, RuntimeError> {
+    let file = context.resolve(path);
+    if !context.capabilities.borrow().can_read(&file) {
+        return Err(RuntimeError::error(line, format!("cannot import '{}': reading '{}' is not allowed", path, file.display())));
+    }
+    let file = match fs::canonicalize(&file) {
+        Ok(file) => file,
+        Err(e) => return Err(RuntimeError::error(line, format!("cannot import '{}': {}", path, e))),
+    };
+
+    if let Some(module) = context.modules.borrow().loaded.get(&file) {
+        return Ok(module.clone());
+    }
+
+    if context.modules.borrow().loading.contains(&file) {
+        let modules = context.modules.borrow();
+        let start = modules.loading.iter().position(|f| *f == file).unwrap_or(0);
+        let mut chain: Vec<String> = modules.loading[start..].iter().map(|f| display_name(f)).collect();
+        chain.push(display_name(&file));
+        return Err(RuntimeError::error(line, format!("import cycle: {}", chain.join(" -> "))));
+    }
+
+    context.modules.borrow_mut().loading.push(file.clone());
+    let result = load(context.for_module(file.clone()), &file, line);
+    context.modules.borrow_mut().loading.pop();
+
+    let module = result?;
+    context.modules.borrow_mut().loaded.insert(file, module.clone());
+
+    Ok(module)
+}
+
+fn load(context: Context, file: &Path, line: usize) -> Result<LiteralValue, RuntimeError> {
+    let name = display_name(file);
+    let source = match fs::read_to_string(file) {
+        Ok(source) => source,
+        Err(e) => return Err(RuntimeError::error(line, format!("cannot import '{}': {}", name, e))),
+    };
+
+    let tokens = Scanner::new(&source)
+        .scan_tokens()
+        .map_err(|e| RuntimeError::error(line, format!("in module '{}': {}", name, e.trim_end())))?;
+    let stmts = Parser::new(tokens, &source)
+        .parse()
+        .map_err(|e| RuntimeError::error(line, format!("in module '{}': {}", name, e)))?;
+    Resolver::new()
+        .resolve(&stmts)
+        .map_err(|e| RuntimeError::error(line, format!("in module '{}': {}", name, e)))?;
+
+    let mut interpreter = Interpreter::with_context(context);
+    interpreter.interpret(stmts.iter().collect())?;
+
+    Ok(LiteralValue::Namespace {
+        name,
+        environment: interpreter.globals(),
+    })
+}
+
+fn display_name(file: &Path) -> String {
+    match file.file_stem() {
+        Some(stem) => stem.to_string_lossy().to_string(),
+        None => file.to_string_lossy().to_string(),
+    }
+}
+
+// Copies the bindings named by a selective import out of a namespace.
+pub fn select(module: &LiteralValue, name: &str, line: usize) -> Result<LiteralValue, RuntimeError> {
+    match module {
+        LiteralValue::Namespace { name: module_name, environment } => {
+            match environment.borrow().get(name) {
+                Some(value) => Ok(value),
+                None => Err(RuntimeError::error(line, format!("module '{}' has no binding '{}'", module_name, name))),
+            }
+        },
+        other => Err(RuntimeError::error(line, format!("cannot import from {}", other.to_type()))),
+    }
+}
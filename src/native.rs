@@ -0,0 +1,180 @@
+use crate::environment::Environment;
+use crate::error::{LoxError, RuntimeError};
+use crate::expr::{Arity, LiteralValue, NamedArgs};
+use crate::sandbox::{Capabilities, Capability};
+use crate::tasks::Scheduler;
+use crate::value::{FromLox, IntoLoxResult, LoxValue};
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+// Native functions written as ordinary Rust closures. The closure's
+// parameter types decide the arity and how arguments are converted; a
+// trailing run of `Option<T>` parameters may be left out by the caller.
+// A closure whose first parameter is `&CallContext` also gets to see the
+// interpreter it was called from.
+
+// What a native function can reach of the code that called it.
+pub struct CallContext {
+    environment: Rc<RefCell<Environment>>,
+}
+
+impl CallContext {
+    // Reads a variable visible where the native was called.
+    pub fn get<T: FromLox>(&self, name: &str) -> Result<T, LoxError> {
+        let value = self.environment.borrow().get(name);
+        match value {
+            Some(value) => T::from_lox(LoxValue(value)).map_err(|e| LoxError::Host(format!("variable '{}': {}", name, e))),
+            None => Err(LoxError::Host(format!("undefined variable '{}'", name))),
+        }
+    }
+
+    // What the script is allowed to touch. Natives registered with the
+    // capabilities they need are checked against it before they run.
+    pub fn capabilities(&self) -> Capabilities {
+        match self.environment.borrow().context() {
+            Some(context) => context.capabilities.borrow().clone(),
+            None => Capabilities::none(),
+        }
+    }
+
+    // The event loop `spawn`, `await` and `sleep` run on.
+    pub(crate) fn scheduler(&self) -> Result<Rc<RefCell<Scheduler>>, String> {
+        match self.environment.borrow().context() {
+            Some(context) => Ok(context.scheduler.clone()),
+            None => Err("tasks are not available here".to_string()),
+        }
+    }
+
+    // Writes out anything `print` has buffered.
+    pub fn flush(&self) {
+        if let Some(context) = self.environment.borrow().context() {
+            let mut output = context.output.borrow_mut();
+            let _ = output.stdout.flush();
+            let _ = output.stderr.flush();
+        }
+    }
+
+    // Calls a Lox callable, such as a function passed in as an argument.
+    pub fn call<T: FromLox>(&self, callee: &LoxValue, args: Vec<LoxValue>) -> Result<T, LoxError> {
+        let args: Vec<LiteralValue> = args.into_iter().map(|arg| arg.0).collect();
+        let (name, arity, fun) = match &callee.0 {
+            LiteralValue::Callable { name, arity, fun, function: _ } => (name, arity, fun),
+            other => return Err(LoxError::Host(format!("{} is not callable", other.to_type()))),
+        };
+        if !arity.accepts(args.len()) {
+            return Err(LoxError::Host(format!("Callable {} expected {} but got {}", name, arity.arguments(), args.len())));
+        }
+
+        let value = fun(self.environment.clone(), &args, &vec![])?;
+        T::from_lox(LoxValue(value)).map_err(LoxError::Host)
+    }
+}
+
+// Implemented for closures taking up to six convertible arguments, with or
+// without a leading `&CallContext`. `Args` only tells the two apart.
+pub trait HostFn<Args>: NativeCall<Args> {}
+
+impl<Args, F: NativeCall<Args>> HostFn<Args> for F {}
+
+// How a closure is called from Lox. Kept out of `HostFn` so hosts can't
+// reach the engine's own values through it.
+pub trait NativeCall<Args>: 'static {
+    fn arity() -> Arity;
+    fn call(&self, context: &CallContext, name: &str, args: &[LiteralValue]) -> Result<LiteralValue, RuntimeError>;
+}
+
+// Parameters are optional from the first one whose whole tail is optional.
+fn arity(optional: &[bool]) -> Arity {
+    let required = optional.iter().rposition(|optional| !optional).map_or(0, |i| i + 1);
+    Arity {
+        min: required,
+        max: Some(optional.len()),
+    }
+}
+
+// Converts the next argument, treating a missing one as nil.
+fn argument<T: FromLox>(name: &str, args: &[LiteralValue], index: &mut usize) -> Result<T, RuntimeError> {
+    let value = args.get(*index).cloned().unwrap_or(LiteralValue::Nil);
+    *index += 1;
+    T::from_lox(LoxValue(value)).map_err(|e| RuntimeError::error(0, format!("argument {} of {}: {}", *index, name, e)))
+}
+
+macro_rules! host_fn {
+    ($($arg:ident),*) => {
+        impl<Fun, R, $($arg,)*> NativeCall<($($arg,)*)> for Fun
+        where
+            Fun: Fn($($arg),*) -> R + 'static,
+            R: IntoLoxResult,
+            $($arg: FromLox,)*
+        {
+            fn arity() -> Arity {
+                arity(&[$($arg::optional()),*])
+            }
+
+            #[allow(unused_variables, unused_mut)]
+            fn call(&self, _context: &CallContext, name: &str, args: &[LiteralValue]) -> Result<LiteralValue, RuntimeError> {
+                let mut index = 0;
+                self($(argument::<$arg>(name, args, &mut index)?),*).into_lox_result().map(|value| value.0)
+            }
+        }
+
+        impl<Fun, R, $($arg,)*> NativeCall<(CallContext, $($arg,)*)> for Fun
+        where
+            Fun: Fn(&CallContext, $($arg),*) -> R + 'static,
+            R: IntoLoxResult,
+            $($arg: FromLox,)*
+        {
+            fn arity() -> Arity {
+                arity(&[$($arg::optional()),*])
+            }
+
+            #[allow(unused_variables, unused_mut)]
+            fn call(&self, context: &CallContext, name: &str, args: &[LiteralValue]) -> Result<LiteralValue, RuntimeError> {
+                let mut index = 0;
+                self(context, $(argument::<$arg>(name, args, &mut index)?),*).into_lox_result().map(|value| value.0)
+            }
+        }
+    };
+}
+
+host_fn!();
+host_fn!(A);
+host_fn!(A, B);
+host_fn!(A, B, C);
+host_fn!(A, B, C, D);
+host_fn!(A, B, C, D, E);
+host_fn!(A, B, C, D, E, G);
+
+// Wraps `fun` as a Lox callable. Natives have no parameter names, so they
+// refuse named arguments.
+pub fn native_fn<Args, F: HostFn<Args>>(name: &str, fun: F) -> LiteralValue {
+    native_fn_requiring(name, &[], fun)
+}
+
+// Like `native_fn`, for a native that reaches outside the interpreter. Every
+// call is checked against the script's capabilities before `fun` runs.
+pub fn native_fn_requiring<Args, F: HostFn<Args>>(name: &str, needs: &[Capability], fun: F) -> LiteralValue {
+    let native_name = name.to_string();
+    let needs = needs.to_vec();
+    let arity = F::arity();
+
+    LiteralValue::Callable {
+        name: name.to_string(),
+        arity,
+        fun: Rc::new(move |environment, args: &Vec<LiteralValue>, named: &NamedArgs| {
+            if let Some((arg_name, _)) = named.first() {
+                return Err(RuntimeError::error(arg_name.line_num, format!("native function {} does not take named arguments", native_name)));
+            }
+            let context = CallContext { environment };
+            if !needs.is_empty() {
+                let capabilities = context.capabilities();
+                for need in &needs {
+                    capabilities.check(*need, &native_name, args).map_err(|e| RuntimeError::error(0, e))?;
+                }
+            }
+            fun.call(&context, &native_name, args)
+        }),
+        function: None,
+    }
+}
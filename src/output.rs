@@ -0,0 +1,68 @@
+use crate::environment::Environment;
+use crate::error::RuntimeError;
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+// Where a run's output goes: `print` writes to `stdout`, and diagnostics
+// the host reports go to `stderr`. Both start out as the process streams;
+// an embedder can point them anywhere, such as an `OutputBuffer`.
+pub struct Output {
+    pub stdout: Box<dyn Write>,
+    pub stderr: Box<dyn Write>,
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Self {
+            stdout: Box::new(io::stdout()),
+            stderr: Box::new(io::stderr()),
+        }
+    }
+}
+
+// An in-memory sink. Clones share the same buffer, so one can be handed to
+// the engine and the other kept to read what was written.
+#[derive(Clone, Default)]
+pub struct OutputBuffer {
+    bytes: Rc<RefCell<Vec<u8>>>,
+}
+
+impl OutputBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.bytes.borrow()).to_string()
+    }
+
+    // Returns what was written so far and empties the buffer.
+    pub fn take(&self) -> String {
+        let bytes = std::mem::take(&mut *self.bytes.borrow_mut());
+        String::from_utf8_lossy(&bytes).to_string()
+    }
+}
+
+impl Write for OutputBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.bytes.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Writes one line of `print` output for code running in `environment`.
+// Without a context there is nowhere configured, so it goes to stdout.
+pub fn print(environment: &Rc<RefCell<Environment>>, text: &str) -> Result<(), RuntimeError> {
+    let context = environment.borrow().context();
+    let result = match context {
+        Some(context) => writeln!(context.output.borrow_mut().stdout, "{}", text),
+        None => writeln!(io::stdout(), "{}", text),
+    };
+
+    result.map_err(|e| RuntimeError::error(0, format!("cannot write output: {}", e)))
+}
@@ -2,23 +2,29 @@ use crate::{scanner::TokenType, Token};
 use crate::expr::{Expr, LiteralValue};
 use crate::expr::Expr::*;
 use crate::scanner::TokenType::*;
-use crate::stmt::Stmt;
+use crate::stmt::{BindingPattern, FunctionSource, MatchArm, Parameters, Pattern, Stmt};
+use std::rc::Rc;
 
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
+    // The text the tokens were scanned from.
+    source: String,
 }
 
 #[derive(Debug)]
 enum FunctionKind {
     Function,
+    Generator,
+    Lambda,
 }
 
 impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self {
+    pub fn new(tokens: Vec<Token>, source: &str) -> Self {
         Self {
             tokens,
             current: 0,
+            source: source.to_string(),
         }
     }
 
@@ -47,27 +53,79 @@ impl Parser {
     fn declaration(&mut self) -> Result<Stmt, String> {
         if self.match_token(Var) {
             self.var_declaration()
-        }else if self.match_token(Fun) {
-            self.function(FunctionKind::Function)
+        }else if self.match_token(Const) {
+            self.const_declaration()
+        }else if self.match_token(Import) {
+            self.import_declaration()
+        }else if self.match_token(Enum) {
+            self.enum_declaration()
+        }else if self.check(Fun) && !self.check_next(LeftParen) {
+            self.advance();
+            if self.match_token(Star) {
+                self.function(FunctionKind::Generator)
+            }else {
+                self.function(FunctionKind::Function)
+            }
         }else {
             self.statement()
         }
     }
 
     fn function(&mut self, kind: FunctionKind) -> Result<Stmt, String> {
+        // Back to the `fun`, and the `*` of a generator.
+        let start = self.current - if matches!(kind, FunctionKind::Generator) { 2 } else { 1 };
         let name = self.consume(Identifier, &format!("Expected {kind:?} name"))?;
 
         self.consume(LeftParen, &format!("Expected '(' after {kind:?} name"))?;
-        let mut params = vec![];
+        let params = self.parameters()?;
+        
+        self.consume(LeftBrace, &format!("Expected '{{' before {kind:?} body."))?;
+        let body = self.function_body(&kind)?;
+
+        Ok(Stmt::Function {
+            name,
+            params,
+            body,
+            generator: matches!(kind, FunctionKind::Generator),
+            source: self.source_from(start),
+        })
+    }
+
+    // Parses a parameter list up to and including the closing ')'.
+    fn parameters(&mut self) -> Result<Parameters, String> {
+        let mut names = vec![];
+        let mut defaults = vec![];
+        let mut patterns = vec![];
+        let mut rest = None;
         if !self.check(RightParen) {
             loop {
-                if params.len() >= 255 {
+                if names.len() >= 255 {
                     let location = self.peek().line_num;
                     return Err(format!("Line {location}: can't have more than 255 parameters"));
                 }
+
+                if self.match_token(Ellipsis) {
+                    rest = Some(self.consume(Identifier, "Expected rest parameter name after '...'")?);
+                    break;
+                }
                 
-                let param = self.consume(Identifier, "Expected parameter name")?;
-                params.push(param);
+                let (param, pattern) = if self.check(LeftBracket) || self.check(LeftBrace) {
+                    let pattern = self.binding_pattern()?;
+                    let line = self.previous().line_num;
+                    (Token::new(Identifier, pattern.to_string(), None, line), Some(pattern))
+                }else {
+                    (self.consume(Identifier, "Expected parameter name")?, None)
+                };
+                let default = if self.match_token(Equal) {
+                    Some(self.conditional()?)
+                }else if defaults.iter().any(|d: &Option<Expr>| d.is_some()) {
+                    return Err(format!("Line {}: parameter '{}' without a default follows a parameter with one", param.line_num, param.lexeme));
+                }else {
+                    None
+                };
+                names.push(param);
+                defaults.push(default);
+                patterns.push(pattern);
 
                 if !self.match_token(Comma) {
                     break;
@@ -75,22 +133,138 @@ impl Parser {
             }
         }
         self.consume(RightParen, "Expected ')' after parameters.")?;
-        
-        self.consume(LeftBrace, &format!("Expected '{{' before {kind:?} body."))?;
 
-        let body = match self.block_statement()? {
-            Stmt::Block { statements } => statements,
-            _ => return Err(format!("Expected body for {kind:?}")),
+        Ok(Parameters {
+            names,
+            defaults,
+            patterns,
+            rest,
+        })
+    }
+
+    fn function_body(&mut self, kind: &FunctionKind) -> Result<Vec<Box<Stmt>>, String> {
+        match self.block_statement()? {
+            Stmt::Block { statements } => Ok(statements),
+            _ => Err(format!("Expected body for {kind:?}")),
+        }
+    }
+
+    fn lambda(&mut self) -> Result<Expr, String> {
+        let start = self.current - 1;
+        self.consume(LeftParen, "Expected '(' after 'fun'.")?;
+        let params = self.parameters()?;
+
+        self.consume(LeftBrace, "Expected '{' before Lambda body.")?;
+        let body = self.function_body(&FunctionKind::Lambda)?;
+
+        Ok(AnonFunction {
+            params,
+            body,
+            source: self.source_from(start),
+        })
+    }
+
+    // `(a, b) => a + b` or `(a) => { ... }`; the opening '(' is already consumed.
+    fn arrow_function(&mut self) -> Result<Expr, String> {
+        let start = self.current - 1;
+        let params = self.parameters()?;
+        self.consume(Arrow, "Expected '=>' after parameters.")?;
+
+        let body = if self.match_token(LeftBrace) {
+            self.function_body(&FunctionKind::Lambda)?
+        }else {
+            let expression = self.assignment()?;
+            vec![Box::new(Stmt::Expression { expression })]
         };
 
-        Ok(Stmt::Function {
-            name,
+        Ok(AnonFunction {
             params,
             body,
+            source: self.source_from(start),
+        })
+    }
+
+    // The source text from the token at `start` up to the current one.
+    fn source_from(&self, start: usize) -> Rc<FunctionSource> {
+        let first = &self.tokens[start];
+        let last = &self.tokens[self.current - 1];
+        Rc::new(FunctionSource {
+            text: self.source[first.offset..last.offset + last.lexeme.len()].to_string(),
+            line: first.line_num,
+        })
+    }
+
+    // Looks past a parenthesised group to tell `(a, b) => ...` apart from a
+    // grouping expression. `start` is the index of the opening '('.
+    fn is_arrow_function(&self, start: usize) -> bool {
+        let mut depth = 0;
+        for (i, token) in self.tokens.iter().enumerate().skip(start) {
+            match token.token_type {
+                LeftParen => depth += 1,
+                RightParen => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return self.tokens.get(i + 1).map(|t| t.token_type) == Some(Arrow);
+                    }
+                },
+                Eof => return false,
+                _ => {},
+            }
+        }
+
+        false
+    }
+
+    // import "path.lox" as name;
+    // import { a, b } from "path.lox";
+    // `as` and `from` are only keywords here, so they stay usable as names.
+    fn import_declaration(&mut self) -> Result<Stmt, String> {
+        let keyword = self.previous();
+        let mut names = vec![];
+        let mut alias = None;
+
+        let path;
+        if self.match_token(LeftBrace) {
+            loop {
+                names.push(self.consume(Identifier, "Expected name to import.")?);
+                if !self.match_token(Comma) {
+                    break;
+                }
+            }
+            self.consume(RightBrace, "Expected '}' after imported names.")?;
+            self.consume_contextual("from", "Expected 'from' after imported names.")?;
+            path = self.consume(StringLit, "Expected module path after 'from'.")?;
+        }else {
+            path = self.consume(StringLit, "Expected module path after 'import'.")?;
+            self.consume_contextual("as", "Expected 'as' after module path.")?;
+            alias = Some(self.consume(Identifier, "Expected module name after 'as'.")?);
+        }
+        self.consume(SemiColon, "Expected ';' after import.")?;
+
+        let path = match LiteralValue::from_token(path) {
+            LiteralValue::StringValue(path) => path,
+            _ => return Err(format!("Line {}: expected module path", keyword.line_num)),
+        };
+
+        Ok(Stmt::Import {
+            keyword,
+            path,
+            alias,
+            names,
         })
     }
 
     fn var_declaration(&mut self) -> Result<Stmt, String> {
+        if self.check(LeftBracket) || self.check(LeftBrace) {
+            let pattern = self.binding_pattern()?;
+            let message = format!("Line {}: destructuring {} needs a value", self.previous().line_num, pattern.to_string());
+            self.consume(Equal, &message)?;
+            let initializer = self.expression()?;
+            self.consume(SemiColon, "Expected ';' after variable declaration")?;
+
+            return Ok(Stmt::Destructure { pattern, initializer });
+        }
+
         let token = self.consume(Identifier, "Expected variable name")?;
 
         let initializer;
@@ -105,6 +279,89 @@ impl Parser {
         Ok( Stmt::Var { name: token, initializer: initializer } )
     }
 
+    // A name, `[a, b, ...rest]` or `{name, age}`. A name may only appear
+    // once in the whole pattern.
+    fn binding_pattern(&mut self) -> Result<BindingPattern, String> {
+        let pattern = self.binding_pattern_part()?;
+
+        let names = pattern.names();
+        for (i, name) in names.iter().enumerate() {
+            if names[..i].iter().any(|seen| seen.lexeme == name.lexeme) {
+                return Err(format!("Line {}: '{}' is bound more than once in {}", name.line_num, name.lexeme, pattern.to_string()));
+            }
+        }
+
+        Ok(pattern)
+    }
+
+    fn binding_pattern_part(&mut self) -> Result<BindingPattern, String> {
+        if self.match_token(LeftBracket) {
+            let bracket = self.previous();
+            let mut elements = vec![];
+            let mut rest = None;
+            while !self.check(RightBracket) {
+                if self.match_token(Ellipsis) {
+                    rest = Some(self.consume(Identifier, "Expected name after '...'")?);
+                    break;
+                }
+                elements.push(self.binding_pattern_part()?);
+
+                if !self.match_token(Comma) {
+                    break;
+                }
+            }
+            self.consume(RightBracket, "Expected ']' after list pattern.")?;
+
+            Ok(BindingPattern::List { bracket, elements, rest })
+        }else if self.match_token(LeftBrace) {
+            let brace = self.previous();
+            let mut names = vec![];
+            while !self.check(RightBrace) {
+                names.push(self.consume(Identifier, "Expected property name in pattern")?);
+
+                if !self.match_token(Comma) {
+                    break;
+                }
+            }
+            self.consume(RightBrace, "Expected '}' after object pattern.")?;
+
+            Ok(BindingPattern::Object { brace, names })
+        }else {
+            Ok(BindingPattern::Name(self.consume(Identifier, "Expected variable name")?))
+        }
+    }
+
+    fn const_declaration(&mut self) -> Result<Stmt, String> {
+        let name = self.consume(Identifier, "Expected constant name")?;
+        self.consume(Equal, &format!("Line {}: constant '{}' needs a value", name.line_num, name.lexeme))?;
+        let initializer = self.expression()?;
+        self.consume(SemiColon, "Expected ';' after constant declaration")?;
+
+        Ok(Stmt::Const { name, initializer })
+    }
+
+    // enum Color { Red, Green, Blue }
+    fn enum_declaration(&mut self) -> Result<Stmt, String> {
+        let name = self.consume(Identifier, "Expected enum name")?;
+        self.consume(LeftBrace, "Expected '{' after enum name.")?;
+
+        let mut variants: Vec<Token> = vec![];
+        while !self.check(RightBrace) {
+            let variant = self.consume(Identifier, "Expected variant name")?;
+            if variants.iter().any(|v| v.lexeme == variant.lexeme) {
+                return Err(format!("Line {}: enum '{}' already has a variant '{}'", variant.line_num, name.lexeme, variant.lexeme));
+            }
+            variants.push(variant);
+
+            if !self.match_token(Comma) {
+                break;
+            }
+        }
+        self.consume(RightBrace, "Expected '}' after enum variants.")?;
+
+        Ok(Stmt::Enum { name, variants })
+    }
+
     fn statement(&mut self) -> Result<Stmt, String> {
         if self.match_token(Print) {
             self.print_statement()
@@ -114,8 +371,20 @@ impl Parser {
             self.if_statement()
         }else if self.match_token(While) {
             self.while_statement()
+        }else if self.match_token(Do) {
+            self.do_while_statement()
+        }else if self.match_token(Break) || self.match_token(Continue) {
+            self.jump_statement()
         }else if self.match_token(For) {
             self.for_statement()
+        }else if self.match_token(Throw) {
+            self.throw_statement()
+        }else if self.match_token(Try) {
+            self.try_statement()
+        }else if self.match_token(Match) {
+            self.match_statement()
+        }else if self.match_token(Yield) {
+            self.yield_statement()
         }
         else {
             self.expression_statement()
@@ -125,6 +394,18 @@ impl Parser {
     fn for_statement(&mut self) -> Result<Stmt, String> {
         self.consume(LeftParen, "Expected '(' after 'for'.")?;
 
+        // `in` is only a keyword here, like `as` and `from` in imports, so
+        // look past the loop variable for it and back up if it isn't there.
+        if self.check(Var) {
+            let start = self.current;
+            self.advance();
+            let is_for_in = self.binding_pattern().is_ok() && self.consume_contextual("in", "").is_ok();
+            self.current = start;
+            if is_for_in {
+                return self.for_in_statement();
+            }
+        }
+
         // Consumes "SMTHNG ;"
         let initializer;
         if self.match_token(SemiColon) {
@@ -157,15 +438,7 @@ impl Parser {
         self.consume(RightParen, "Expected ')' after for clauses")?;
 
         let mut body = self.statement()?;
-        if let Some(inc) = increment {
-            body = Stmt::Block {
-                statements: vec![
-                    Box::new(body), 
-                    Box::new(Stmt::Expression { expression: inc }),
-                ],
-            };
-        }
-        
+
         let cond;
         match condition {
             None => cond = Expr::Literal { value: LiteralValue::True },
@@ -174,6 +447,7 @@ impl Parser {
         body = Stmt::WhileStmt {
             condition: cond,
             body: Box::new(body),
+            increment,
         };
 
         if let Some(init) = initializer {
@@ -188,6 +462,48 @@ impl Parser {
         Ok(body)
     }
 
+    fn throw_statement(&mut self) -> Result<Stmt, String> {
+        let keyword = self.previous();
+        let value = self.expression()?;
+        self.consume(SemiColon, "Expected ';' after thrown value.")?;
+
+        Ok(Stmt::Throw {
+            keyword,
+            value,
+        })
+    }
+
+    fn try_statement(&mut self) -> Result<Stmt, String> {
+        let line = self.previous().line_num;
+        self.consume(LeftBrace, "Expected '{' after 'try'.")?;
+        let body = self.block_statement()?;
+
+        let mut catch = None;
+        if self.match_token(Catch) {
+            self.consume(LeftParen, "Expected '(' after 'catch'.")?;
+            let name = self.consume(Identifier, "Expected name of the caught value.")?;
+            self.consume(RightParen, "Expected ')' after caught value name.")?;
+            self.consume(LeftBrace, "Expected '{' after 'catch (...)'.")?;
+            catch = Some((name, Box::new(self.block_statement()?)));
+        }
+
+        let mut finally = None;
+        if self.match_token(Finally) {
+            self.consume(LeftBrace, "Expected '{' after 'finally'.")?;
+            finally = Some(Box::new(self.block_statement()?));
+        }
+
+        if catch.is_none() && finally.is_none() {
+            return Err(format!("Line {line}: expected 'catch' or 'finally' after try block"));
+        }
+
+        Ok(Stmt::Try {
+            body: Box::new(body),
+            catch,
+            finally,
+        })
+    }
+
     fn while_statement(&mut self) -> Result<Stmt, String> {
         self.consume(LeftParen, "Expected '('.")?;
         let condition = self.expression()?;
@@ -196,10 +512,36 @@ impl Parser {
 
         Ok(Stmt::WhileStmt { 
             condition, 
-            body: Box::from(body) 
+            body: Box::from(body),
+            increment: None,
+        })
+    }
+
+    fn do_while_statement(&mut self) -> Result<Stmt, String> {
+        let body = self.statement()?;
+        self.consume(While, "Expected 'while' after do body.")?;
+        self.consume(LeftParen, "Expected '(' after 'while'.")?;
+        let condition = self.expression()?;
+        self.consume(RightParen, "Expected ')' after condition.")?;
+        self.consume(SemiColon, "Expected ';' after do-while condition.")?;
+
+        Ok(Stmt::DoWhile {
+            body: Box::new(body),
+            condition,
         })
     }
 
+    // `break;` or `continue;`
+    fn jump_statement(&mut self) -> Result<Stmt, String> {
+        let keyword = self.previous();
+        self.consume(SemiColon, &format!("Expected ';' after '{}'.", keyword.lexeme))?;
+
+        match keyword.token_type {
+            Break => Ok(Stmt::Break { keyword }),
+            _ => Ok(Stmt::Continue { keyword }),
+        }
+    }
+
     fn if_statement(&mut self) -> Result<Stmt, String> {
         self.consume(LeftParen, "Expected '('.")?;
         let predicate = self.expression()?;
@@ -257,7 +599,7 @@ impl Parser {
     }
 
     fn assignment(&mut self) -> Result<Expr, String> {
-        let expr = self.or()?;
+        let expr = self.conditional()?;
 
         if self.match_token(Equal) {
             let _equals = self.previous();
@@ -270,8 +612,72 @@ impl Parser {
                         value: Box::from(value)
                     });
                 }
+                Index { object, bracket, index } => {
+                    return Ok(IndexAssign {
+                        object,
+                        bracket,
+                        index,
+                        value: Box::from(value),
+                    });
+                }
+                Get { object, name } => {
+                    return Ok(Set {
+                        object,
+                        name,
+                        value: Box::from(value),
+                    });
+                }
                 _ => return Err("invalid assignment target.".to_string()),
             }
+        }else if self.match_tokens(&[PlusEqual, MinusEqual, StarEqual, SlashEqual, PercentEqual]) {
+            let operator = self.previous();
+            let value = self.assignment()?;
+
+            match expr {
+                Variable { name: _ } | Index { object: _, bracket: _, index: _ } | Get { object: _, name: _ } => {
+                    return Ok(CompoundAssign {
+                        target: Box::from(expr),
+                        operator,
+                        value: Box::from(value),
+                    });
+                }
+                _ => return Err(format!("Line {}: invalid assignment target.", operator.line_num)),
+            }
+        }
+
+        Ok(expr)
+    }
+
+    fn conditional(&mut self) -> Result<Expr, String> {
+        let expr = self.coalesce()?;
+
+        if self.match_token(Question) {
+            let then = self.expression()?;
+            self.consume(Colon, "Expected ':' in conditional expression.")?;
+            let els = self.conditional()?;
+
+            return Ok(Conditional {
+                condition: Box::new(expr),
+                then: Box::new(then),
+                els: Box::new(els),
+            });
+        }
+
+        Ok(expr)
+    }
+
+    fn coalesce(&mut self) -> Result<Expr, String> {
+        let mut expr = self.or()?;
+
+        while self.match_token(QuestionQuestion) {
+            let operator = self.previous();
+            let right = self.or()?;
+
+            expr = Logical {
+                left: Box::new(expr),
+                operator,
+                right: Box::new(right),
+            };
         }
 
         Ok(expr)
@@ -312,10 +718,10 @@ impl Parser {
     }
 
     fn equality(&mut self) -> Result<Expr, String> {
-        let mut expr: Expr = self.comparison()?;
+        let mut expr: Expr = self.bitwise()?;
         while self.match_tokens(&[BangEqual, EqualEqual]) {
             let operator = self.previous();
-            let rhs = self.comparison()?;
+            let rhs = self.bitwise()?;
             expr = Expr::Binary {
                 left: Box::new(expr),
                 right: Box::new(rhs),
@@ -326,6 +732,22 @@ impl Parser {
         Ok(expr)
     }
 
+    fn bitwise(&mut self) -> Result<Expr, String> {
+        let mut expr = self.comparison()?;
+
+        while self.match_tokens(&[Ampersand, Pipe, Caret, LessLess, GreaterGreater]) {
+            let op = self.previous();
+            let rhs = self.comparison()?;
+            expr = Binary {
+                left: Box::from(expr),
+                operator: op,
+                right: Box::from(rhs),
+            };
+        }
+
+        Ok(expr)
+    }
+
     fn comparison(&mut self) -> Result<Expr, String> {
         let mut expr = self.term()?;
 
@@ -361,7 +783,7 @@ impl Parser {
     fn factor(&mut self) -> Result<Expr, String> {
         let mut expr = self.unary()?;
 
-        while self.match_tokens(&[Slash, Star]) {
+        while self.match_tokens(&[Slash, Star, Percent]) {
             let op = self.previous();
             let rhs = self.unary()?;
             expr = Binary {
@@ -375,7 +797,28 @@ impl Parser {
     }
 
     fn unary(&mut self) -> Result<Expr, String> {
-        if self.match_tokens(&[TokenType::Bang, TokenType::Minus]) {
+        if self.match_tokens(&[PlusPlus, MinusMinus]) {
+            let op = self.previous();
+            let target = self.unary()?;
+            self.update(target, op, true)
+        }else if self.match_token(Await) {
+            let keyword = self.previous();
+            let value = self.unary()?;
+            Ok(Expr::Await {
+                keyword,
+                value: Box::new(value),
+            })
+        }else if self.match_token(Spawn) {
+            let keyword = self.previous();
+            let call = self.call()?;
+            if !matches!(call, Call { callee: _, paren: _, arguments: _, named: _ }) {
+                return Err(format!("Line {}: expected a call after 'spawn'", keyword.line_num));
+            }
+            Ok(Expr::Spawn {
+                keyword,
+                call: Box::new(call),
+            })
+        }else if self.match_tokens(&[TokenType::Bang, TokenType::Minus, TokenType::Tilde]) {
             let op = self.previous();
             let rhs = self.unary()?;
             Ok(Unary {
@@ -393,22 +836,63 @@ impl Parser {
         loop {
             if self.match_token(LeftParen) {
                 expr = self.finish_call(expr)?;
+            }else if self.match_token(Dot) {
+                let name = self.consume(Identifier, "Expected property name after '.'.")?;
+                expr = Get {
+                    object: Box::new(expr),
+                    name,
+                };
+            }else if self.match_token(LeftBracket) {
+                let index = self.expression()?;
+                let bracket = self.consume(RightBracket, "Expected ']' after index.")?;
+                expr = Index {
+                    object: Box::new(expr),
+                    bracket,
+                    index: Box::new(index),
+                };
             }else {
                 break;
             }
         }
         // Apply to arguments
 
+        if self.match_tokens(&[PlusPlus, MinusMinus]) {
+            let op = self.previous();
+            return self.update(expr, op, false);
+        }
+
         Ok(expr)
     }
 
+    fn update(&mut self, target: Expr, operator: Token, prefix: bool) -> Result<Expr, String> {
+        match target {
+            Variable { name: _ } | Index { object: _, bracket: _, index: _ } | Get { object: _, name: _ } => Ok(Update {
+                target: Box::from(target),
+                operator,
+                prefix,
+            }),
+            _ => Err(format!("Line {}: invalid increment target.", operator.line_num)),
+        }
+    }
+
     fn finish_call(&mut self, callee: Expr) -> Result<Expr, String> {
         let mut arguments = vec![];
+        let mut named = vec![];
         if !self.check(RightParen) {
             loop {
-                let arg = self.expression()?;
-                arguments.push(arg);
-                if arguments.len() >= 255 {
+                if self.check(Identifier) && self.check_next(Colon) {
+                    let name = self.advance();
+                    self.advance();
+                    let arg = self.expression()?;
+                    named.push((name, arg));
+                }else if named.is_empty() {
+                    let arg = self.expression()?;
+                    arguments.push(arg);
+                }else {
+                    let location = self.peek().line_num;
+                    return Err(format!("Line {location}: positional argument follows named argument"));
+                }
+                if arguments.len() + named.len() >= 255 {
                     let location = self.peek().line_num;
                     return Err(format!("line: {location} cannot have more than 255 arguments"));
                 }
@@ -418,11 +902,12 @@ impl Parser {
             }
         }
 
-        self.consume(RightParen, "Expect ')' after function arguments")?;
+        let paren = self.consume(RightParen, "Expect ')' after function arguments")?;
         Ok(Expr::Call {
             callee: Box::new(callee),
             arguments,
-            paren: Token::new(LeftParen, "".to_string(), None, 0),
+            named,
+            paren,
         })
     }
 
@@ -431,6 +916,32 @@ impl Parser {
 
         let result;
         match token.token_type {
+            LeftParen if self.is_arrow_function(self.current) => {
+                self.advance();
+                result = self.arrow_function()?;
+            },
+            Fun => {
+                self.advance();
+                result = self.lambda()?;
+            },
+            LeftBracket => {
+                self.advance();
+                let mut elements = vec![];
+                if !self.check(RightBracket) {
+                    loop {
+                        elements.push(self.expression()?);
+                        if !self.match_token(Comma) {
+                            break;
+                        }
+                    }
+                }
+                self.consume(RightBracket, "Expected ']' after list elements.")?;
+                result = List { elements };
+            },
+            LeftBrace => {
+                self.advance();
+                result = self.map_literal()?;
+            },
             LeftParen =>  {
                 self.advance();
                 let expr = self.expression()?;
@@ -458,10 +969,45 @@ impl Parser {
         Ok(result)
     }
 
+    // `{name: value, "any key": value}`. Only reached in expression
+    // position; a statement starting with `{` is a block.
+    fn map_literal(&mut self) -> Result<Expr, String> {
+        let mut entries: Vec<(String, Expr)> = vec![];
+        if !self.check(RightBrace) {
+            loop {
+                let key = self.advance();
+                let name = match (key.token_type, &key.literal) {
+                    (Identifier, _) => key.lexeme.clone(),
+                    (StringLit, Some(crate::scanner::LiteralValue::StringValue(value))) => value.clone(),
+                    _ => return Err(format!("Line {}: expected a name or a string as map key", key.line_num)),
+                };
+                if entries.iter().any(|(k, _)| *k == name) {
+                    return Err(format!("Line {}: duplicate map key '{}'", key.line_num, name));
+                }
+                self.consume(Colon, "Expected ':' after map key.")?;
+                entries.push((name, self.expression()?));
+
+                if !self.match_token(Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(RightBrace, "Expected '}' after map entries.")?;
+
+        Ok(Expr::Map { entries })
+    }
+
     fn check(&mut self, typ: TokenType) -> bool {
         self.peek().token_type == typ
     }
 
+    fn check_next(&mut self, typ: TokenType) -> bool {
+        match self.tokens.get(self.current + 1) {
+            Some(token) => token.token_type == typ,
+            None => false,
+        }
+    }
+
     fn match_token(&mut self, typ: TokenType) -> bool {
         if self.is_at_end() {
             return false;
@@ -488,6 +1034,15 @@ impl Parser {
 
     }
 
+    fn consume_contextual(&mut self, word: &str, msg: &str) -> Result<Token, String> {
+        let token = self.peek();
+        if token.token_type == Identifier && token.lexeme == word {
+            Ok(self.advance())
+        }else {
+            Err(format!("Line {}: {}", token.line_num, msg))
+        }
+    }
+
     fn match_tokens(&mut self, typs: &[TokenType]) -> bool {
         for typ in typs {
             if self.match_token(*typ) {
@@ -518,6 +1073,127 @@ impl Parser {
         self.peek().token_type == TokenType::Eof
     }
 
+    fn yield_statement(&mut self) -> Result<Stmt, String> {
+        let keyword = self.previous();
+        let value = if self.check(SemiColon) {
+            Literal { value: LiteralValue::Nil }
+        }else {
+            self.expression()?
+        };
+        self.consume(SemiColon, "Expected ';' after yield value.")?;
+
+        Ok(Stmt::Yield { keyword, value })
+    }
+
+    // for (var x in xs) body; the '(' is already consumed.
+    fn for_in_statement(&mut self) -> Result<Stmt, String> {
+        self.consume(Var, "Expected 'var' in for-in loop.")?;
+        let target = self.binding_pattern()?;
+        self.consume_contextual("in", "Expected 'in' after loop variable.")?;
+        let iterable = self.expression()?;
+        self.consume(RightParen, "Expected ')' after for-in clause")?;
+        let body = self.statement()?;
+
+        Ok(Stmt::ForIn {
+            target,
+            iterable,
+            body: Box::new(body),
+        })
+    }
+
+    // match (subject) { pattern => statement, ... }
+    fn match_statement(&mut self) -> Result<Stmt, String> {
+        let keyword = self.previous();
+        self.consume(LeftParen, "Expected '(' after 'match'.")?;
+        let subject = self.expression()?;
+        self.consume(RightParen, "Expected ')' after match subject.")?;
+        self.consume(LeftBrace, "Expected '{' before match arms.")?;
+
+        let mut arms = vec![];
+        while !self.check(RightBrace) && !self.is_at_end() {
+            let pattern = self.pattern()?;
+            let guard = if self.match_token(If) {
+                Some(self.expression()?)
+            }else {
+                None
+            };
+            self.consume(Arrow, "Expected '=>' after pattern.")?;
+            let body = self.statement()?;
+            arms.push(MatchArm {
+                pattern,
+                guard,
+                body: Box::new(body),
+            });
+
+            self.match_token(Comma);
+        }
+        self.consume(RightBrace, "Expected '}' after match arms.")?;
+
+        if arms.is_empty() {
+            return Err(format!("Line {}: match needs at least one arm", keyword.line_num));
+        }
+
+        Ok(Stmt::Match {
+            keyword,
+            subject,
+            arms,
+        })
+    }
+
+    fn pattern(&mut self) -> Result<Pattern, String> {
+        let token = self.peek();
+        if token.token_type == Identifier && token.lexeme == "_" {
+            self.advance();
+            return Ok(Pattern::Wildcard);
+        }
+
+        if token.token_type == Identifier {
+            // An enum variant, possibly reached through a module: lib.Color.Red
+            let name = self.advance();
+            if !self.check(Dot) {
+                return Ok(Pattern::Binding(name));
+            }
+            let mut path = Variable { name };
+            while self.match_token(Dot) {
+                let name = self.consume(Identifier, "Expected variant name after '.'.")?;
+                path = Get {
+                    object: Box::new(path),
+                    name,
+                };
+            }
+            return Ok(Pattern::Value(path));
+        }
+
+        let start = self.pattern_literal()?;
+        if self.match_tokens(&[DotDot, DotDotEqual]) {
+            let inclusive = self.previous().token_type == DotDotEqual;
+            let end = self.pattern_literal()?;
+            return Ok(Pattern::Range { start, end, inclusive });
+        }
+
+        Ok(Pattern::Value(start))
+    }
+
+    fn pattern_literal(&mut self) -> Result<Expr, String> {
+        let negative = self.match_token(Minus);
+        let token = self.peek();
+        match token.token_type {
+            Number => {
+                self.advance();
+                let value = match LiteralValue::from_token(token) {
+                    LiteralValue::Number(x) if negative => LiteralValue::Number(-x),
+                    value => value,
+                };
+                Ok(Literal { value })
+            },
+            StringLit | True | False | Nil if !negative => {
+                self.advance();
+                Ok(Literal { value: LiteralValue::from_token(token) })
+            },
+            _ => Err(format!("Line {}: expected a literal, range, enum variant, name or '_' as pattern", token.line_num)),
+        }
+    }
+
     fn synchronize(&mut self) {
         self.advance();
 
@@ -527,7 +1203,7 @@ impl Parser {
             }
 
             match self.peek().token_type {
-                Class | Fun | Var | For | If | While | Print | Return => return,
+                Class | Fun | Var | Const | For | If | While | Do | Break | Continue | Print | Return | Throw | Try | Import | Enum | Match | Yield => return,
                 _ => {}
             }
 
@@ -581,7 +1257,7 @@ mod tests {
             None,
             0
         );
-        let mut parser = Parser::new(vec![print, one, plus, two, semicolon, eof]);
+        let mut parser = Parser::new(vec![print, one, plus, two, semicolon, eof], "");
         let statements = parser.parse().unwrap();
         let string_expr = statements[0].to_string();
 
@@ -593,7 +1269,7 @@ mod tests {
         let source = "1.0 + 2.0 == 5.0 + 7.0;";
         let mut scanner = Scanner::new(source);
         let tokens = scanner.scan_tokens().unwrap();
-        let mut parser = Parser::new(tokens);
+        let mut parser = Parser::new(tokens, source);
         let parsed_expr = parser.parse().unwrap();
         let string_expr = parsed_expr[0].to_string();
 
@@ -605,10 +1281,313 @@ mod tests {
         let source = "1.0 == (2.0 + 2.0);";
         let mut scanner = Scanner::new(source);
         let tokens = scanner.scan_tokens().unwrap();
-        let mut parser = Parser::new(tokens);
+        let mut parser = Parser::new(tokens, source);
         let parsed_expr = parser.parse().unwrap();
         let string_expr = parsed_expr[0].to_string();
 
         assert_eq!(string_expr, "(== 1 (group (+ 2 2)))");
     }
+
+    #[test]
+    fn compound_assignment_and_increments() {
+        let source = "i += 2 * 3; ++i; i--;";
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens, source);
+        let parsed_expr = parser.parse().unwrap();
+
+        assert_eq!(parsed_expr[0].to_string(), "((var i) += (* 2 3))");
+        assert_eq!(parsed_expr[1].to_string(), "(++ (var i))");
+        assert_eq!(parsed_expr[2].to_string(), "((var i) --)");
+    }
+
+    #[test]
+    fn conditional_and_coalesce() {
+        let source = "a ?? b ? 1 : c ? 2 : 3;";
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens, source);
+        let parsed_expr = parser.parse().unwrap();
+        let string_expr = parsed_expr[0].to_string();
+
+        assert_eq!(string_expr, "(? (QuestionQuestion ?? None (var a) (var b)) 1 (? (var c) 2 3))");
+    }
+
+    #[test]
+    fn anonymous_functions() {
+        let source = "var f = fun (a, b) { a + b; }; var g = (x) => x * 2; (1 + 2);";
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens, source);
+        let parsed = parser.parse().unwrap();
+
+        match &parsed[0] {
+            crate::stmt::Stmt::Var { name: _, initializer } => assert_eq!(initializer.to_string(), "(fun (a b))"),
+            _ => panic!("expected a var declaration"),
+        }
+        match &parsed[1] {
+            crate::stmt::Stmt::Var { name: _, initializer } => assert_eq!(initializer.to_string(), "(fun (x))"),
+            _ => panic!("expected a var declaration"),
+        }
+        assert_eq!(parsed[2].to_string(), "(group (+ 1 2))");
+    }
+
+    #[test]
+    fn default_and_rest_parameters() {
+        let source = "fun f(a, b = a * 2, ...rest) { a; }";
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens, source);
+        let parsed = parser.parse().unwrap();
+
+        match &parsed[0] {
+            crate::stmt::Stmt::Function { name: _, params, body: _, generator: _, source: _ } => {
+                assert_eq!(params.names.len(), 2);
+                assert!(params.defaults[0].is_none());
+                assert_eq!(params.defaults[1].as_ref().unwrap().to_string(), "(* (var a) 2)");
+                assert_eq!(params.rest.as_ref().unwrap().lexeme, "rest");
+                assert_eq!(params.arity().to_string(), "at least 1");
+            },
+            _ => panic!("expected a function declaration"),
+        }
+
+        let source = "fun g(a = 1, b) { a; }";
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens, source);
+        assert!(parser.parse().is_err());
+    }
+
+    #[test]
+    fn list_literal_and_index() {
+        let source = "[1, 2][0] = [][1];";
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens, source);
+        let parsed_expr = parser.parse().unwrap();
+
+        assert_eq!(parsed_expr[0].to_string(), "((index (list 1 2) 0) = (index (list ) 1))");
+    }
+
+    #[test]
+    fn named_arguments() {
+        let source = "f(1, y: 2, z: 3);";
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens, source);
+        let parsed_expr = parser.parse().unwrap();
+
+        assert_eq!(parsed_expr[0].to_string(), "((var f) [1] {y: 2, z: 3})");
+
+        let source = "f(y: 2, 1);";
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens, source);
+        assert!(parser.parse().is_err());
+    }
+
+    #[test]
+    fn try_requires_catch_or_finally() {
+        let source = "try { throw 1; } catch (e) { print e.message; } finally { print 2; }";
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens, source);
+        let parsed = parser.parse().unwrap();
+        assert_eq!(parsed[0].to_string(), "(try (block (throw 1)) (catch e (block (print (. (var e) message)))) (finally (block (print 2))))");
+
+        let source = "try { print 1; }";
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens, source);
+        assert!(parser.parse().is_err());
+    }
+
+    #[test]
+    fn import_forms() {
+        let source = "import \"lib/math.lox\" as math; import { sqrt, pi } from \"lib/math.lox\"; var as = 1;";
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens, source);
+        let parsed = parser.parse().unwrap();
+
+        match &parsed[0] {
+            crate::stmt::Stmt::Import { keyword: _, path, alias, names } => {
+                assert_eq!(path, "lib/math.lox");
+                assert_eq!(alias.as_ref().unwrap().lexeme, "math");
+                assert!(names.is_empty());
+            },
+            _ => panic!("expected an import"),
+        }
+        match &parsed[1] {
+            crate::stmt::Stmt::Import { keyword: _, path: _, alias, names } => {
+                assert!(alias.is_none());
+                let names: Vec<&str> = names.iter().map(|n| n.lexeme.as_str()).collect();
+                assert_eq!(names, vec!["sqrt", "pi"]);
+            },
+            _ => panic!("expected an import"),
+        }
+        assert_eq!(parsed[2].to_string(), "(var as)");
+    }
+
+    #[test]
+    fn bitwise_binds_between_equality_and_comparison() {
+        let source = "1 | 2 == 3 & 4 < 5;";
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens, source);
+        let parsed_expr = parser.parse().unwrap();
+        let string_expr = parsed_expr[0].to_string();
+
+        assert_eq!(string_expr, "(== (| 1 2) (& 3 (< 4 5)))");
+    }
+
+    #[test]
+    fn enum_for_in_and_match() {
+        let source = "enum Color { Red, Green } for (var c in Color) print c; match (x) { 1..=3 => print 1; Color.Red => {} _ => print 2; }";
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens, source);
+        let parsed = parser.parse().unwrap();
+
+        assert_eq!(parsed[0].to_string(), "(enum Color Red Green)");
+        assert_eq!(parsed[1].to_string(), "(for c in (var Color) (print (var c)))");
+        assert_eq!(parsed[2].to_string(), "(match (var x) ((..= 1 3) (print 1)) ((. (var Color) Red) (block )) (_ (print 2)))");
+    }
+
+    #[test]
+    fn binding_patterns_take_guards() {
+        let source = "match (x) { n if n > 10 => print n; n => print 0; }";
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens, source);
+        let parsed = parser.parse().unwrap();
+
+        assert_eq!(parsed[0].to_string(), "(match (var x) (n if (> (var n) 10) (print (var n))) (n (print 0)))");
+    }
+
+    #[test]
+    fn patterns_must_be_literals_variants_or_names() {
+        let source = "match (x) { (y) => print 1; }";
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens, source);
+        assert!(parser.parse().is_err());
+    }
+
+    #[test]
+    fn destructuring_patterns() {
+        let source = "var [a, [b], ...c] = xs; var {name, age} = p; fun f([x, y], z) {} for (var [k, v] in pairs) print k;";
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens, source);
+        let parsed = parser.parse().unwrap();
+
+        assert_eq!(parsed[0].to_string(), "(var [a, [b], ...c] (var xs))");
+        assert_eq!(parsed[1].to_string(), "(var {name, age} (var p))");
+        match &parsed[2] {
+            crate::stmt::Stmt::Function { name: _, params, body: _, generator: _, source: _ } => {
+                assert_eq!(params.names[0].lexeme, "[x, y]");
+                assert!(params.patterns[0].is_some());
+                assert!(params.patterns[1].is_none());
+            },
+            _ => panic!("expected a function"),
+        }
+        assert_eq!(parsed[3].to_string(), "(for [k, v] in (var pairs) (print (var k)))");
+
+        let source = "var [a, a] = xs;";
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens, source);
+        assert!(parser.parse().is_err());
+    }
+
+    #[test]
+    fn generator_declarations() {
+        let source = "var x;\nfun* g(n) {\n  yield n; yield;\n}";
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens, source);
+        let parsed = parser.parse().unwrap();
+
+        match &parsed[1] {
+            crate::stmt::Stmt::Function { name, params: _, body, generator, source } => {
+                assert_eq!(name.lexeme, "g");
+                assert!(*generator);
+                assert_eq!(source.text, "fun* g(n) {\n  yield n; yield;\n}");
+                assert_eq!(source.line, 2);
+                assert_eq!(body[0].to_string(), "(yield (var n))");
+                assert_eq!(body[1].to_string(), "(yield nil)");
+            },
+            _ => panic!("expected a function"),
+        }
+    }
+
+    #[test]
+    fn spawn_and_await() {
+        let source = "var t = spawn f(1); await t; spawn f;";
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens, source);
+        let result = parser.parse();
+
+        assert_eq!(result.unwrap_err(), "Line 1: expected a call after 'spawn'");
+
+        let source = "await spawn f(1);";
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens, source);
+        let parsed = parser.parse().unwrap();
+
+        assert_eq!(parsed[0].to_string(), "(await (spawn ((var f) [1])))");
+    }
+
+    #[test]
+    fn map_literals() {
+        let source = "var m = {a: 1, \"b c\": [2]}; var e = {};";
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens, source);
+        let parsed = parser.parse().unwrap();
+
+        match &parsed[0] {
+            crate::stmt::Stmt::Var { name: _, initializer } => assert_eq!(initializer.to_string(), "(map (a 1) (b c (list 2)))"),
+            _ => panic!("expected a declaration"),
+        }
+
+        let source = "var m = {a: 1, a: 2};";
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens, source);
+        assert_eq!(parser.parse().unwrap_err(), "Line 1: duplicate map key 'a'");
+    }
+
+    #[test]
+    fn property_assignment() {
+        let source = "a.b = 1; a.b += 2; a.b++;";
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens, source);
+        let parsed = parser.parse().unwrap();
+
+        assert_eq!(parsed[0].to_string(), "((. (var a) b) = 1)");
+        assert_eq!(parsed[1].to_string(), "((. (var a) b) += 2)");
+        assert_eq!(parsed[2].to_string(), "((. (var a) b) ++)");
+    }
+
+    #[test]
+    fn do_while_and_jumps() {
+        let source = "do { break; continue; } while (x);";
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens, source);
+        let parsed = parser.parse().unwrap();
+
+        assert_eq!(parsed[0].to_string(), "(do (block (break)(continue)) while (var x))");
+
+        let source = "do print 1; while (x)";
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens, source);
+        assert!(parser.parse().is_err());
+    }
 }
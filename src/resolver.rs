@@ -0,0 +1,439 @@
+use crate::expr::Expr;
+use crate::generator;
+use crate::scanner::Token;
+use crate::stmt::{MatchArm, Parameters, Pattern, Stmt};
+use std::collections::HashMap;
+
+// Static checks run after parsing and before anything executes. It mirrors
+// the interpreter's lexical scopes, remembering which names are constants,
+// so assignments to a constant that is visible at that point in the source
+// are rejected up front. Anything it can't see (globals declared later or
+// in an earlier REPL line) is still caught by the Environment at runtime.
+pub struct Resolver {
+    scopes: Vec<HashMap<String, Binding>>,
+    // One entry per function being resolved: is it a generator?
+    functions: Vec<bool>,
+    // Set while resolving a statement a generator can suspend on, until
+    // its `await` is reached.
+    suspending_await: bool,
+    // How many loops enclose the statement, within the current function.
+    loops: usize,
+    errors: Vec<String>,
+}
+
+#[derive(Clone, PartialEq)]
+enum Binding {
+    Variable,
+    Constant,
+    // Enums are constants whose variants are known, so matches over them
+    // can be checked for missing arms.
+    Enum(Vec<String>),
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Self {
+            scopes: vec![HashMap::new()],
+            functions: vec![],
+            suspending_await: false,
+            loops: 0,
+            errors: vec![],
+        }
+    }
+
+    pub fn resolve(&mut self, stmts: &[Stmt]) -> Result<(), String> {
+        for stmt in stmts {
+            self.statement(stmt);
+        }
+
+        if !self.errors.is_empty() {
+            return Err(self.errors.join("\n"));
+        }
+
+        Ok(())
+    }
+
+    fn statement(&mut self, stmt: &Stmt) {
+        self.suspending_await = generator::is_await_statement(stmt);
+
+        match stmt {
+            Stmt::Expression { expression } => self.expression(expression),
+            Stmt::Print { expression } => self.expression(expression),
+            Stmt::Var { name, initializer } => {
+                self.expression(initializer);
+                self.declare(name, Binding::Variable);
+            },
+            Stmt::Destructure { pattern, initializer } => {
+                self.expression(initializer);
+                for name in pattern.names() {
+                    self.declare(name, Binding::Variable);
+                }
+            },
+            Stmt::Const { name, initializer } => {
+                self.expression(initializer);
+                self.declare(name, Binding::Constant);
+            },
+            Stmt::Block { statements } => {
+                self.scopes.push(HashMap::new());
+                for stmt in statements {
+                    self.statement(stmt);
+                }
+                self.scopes.pop();
+            },
+            Stmt::IfStmt { predicate, then, els } => {
+                self.expression(predicate);
+                self.statement(then);
+                if let Some(els) = els {
+                    self.statement(els);
+                }
+            },
+            Stmt::WhileStmt { condition, body, increment } => {
+                self.expression(condition);
+                self.loop_body(body);
+                if let Some(increment) = increment {
+                    self.expression(increment);
+                }
+            },
+            Stmt::DoWhile { body, condition } => {
+                self.loop_body(body);
+                self.expression(condition);
+            },
+            Stmt::Break { keyword } | Stmt::Continue { keyword } => {
+                if self.loops == 0 {
+                    self.errors.push(format!("Line {}: '{}' outside a loop", keyword.line_num, keyword.lexeme));
+                }
+            },
+            Stmt::Function { name, params, body, generator, source: _ } => {
+                self.declare(name, Binding::Variable);
+                self.function(params, body, *generator);
+            },
+            Stmt::Yield { keyword, value } => {
+                if self.functions.last() != Some(&true) {
+                    self.errors.push(format!("Line {}: 'yield' outside a generator", keyword.line_num));
+                }
+                self.expression(value);
+            },
+            Stmt::Import { keyword: _, path: _, alias, names } => {
+                if let Some(alias) = alias {
+                    self.declare(alias, Binding::Variable);
+                }
+                for name in names {
+                    self.declare(name, Binding::Variable);
+                }
+            },
+            Stmt::Throw { keyword: _, value } => self.expression(value),
+            Stmt::Try { body, catch, finally } => {
+                self.statement(body);
+                if let Some((name, handler)) = catch {
+                    self.scopes.push(HashMap::new());
+                    self.declare(name, Binding::Variable);
+                    self.statement(handler);
+                    self.scopes.pop();
+                }
+                if let Some(finally) = finally {
+                    self.statement(finally);
+                }
+            },
+            Stmt::Enum { name, variants } => {
+                let variants = variants.iter().map(|v| v.lexeme.clone()).collect();
+                self.declare(name, Binding::Enum(variants));
+            },
+            Stmt::ForIn { target, iterable, body } => {
+                self.expression(iterable);
+                self.scopes.push(HashMap::new());
+                for name in target.names() {
+                    self.declare(name, Binding::Variable);
+                }
+                self.loop_body(body);
+                self.scopes.pop();
+            },
+            Stmt::Match { keyword, subject, arms } => {
+                self.expression(subject);
+                for arm in arms {
+                    self.scopes.push(HashMap::new());
+                    match &arm.pattern {
+                        Pattern::Wildcard => {},
+                        Pattern::Value(value) => self.expression(value),
+                        Pattern::Range { start, end, inclusive: _ } => {
+                            self.expression(start);
+                            self.expression(end);
+                        },
+                        Pattern::Binding(name) => self.declare(name, Binding::Variable),
+                    }
+                    if let Some(guard) = &arm.guard {
+                        self.expression(guard);
+                    }
+                    self.statement(&arm.body);
+                    self.scopes.pop();
+                }
+                self.check_exhaustive(keyword, arms);
+            },
+        }
+    }
+
+    fn loop_body(&mut self, body: &Stmt) {
+        self.loops += 1;
+        self.statement(body);
+        self.loops -= 1;
+    }
+
+    fn function(&mut self, params: &Parameters, body: &[Box<Stmt>], generator: bool) {
+        // A function body can't break out of a loop it is defined in.
+        let loops = std::mem::take(&mut self.loops);
+        self.functions.push(generator);
+        self.scopes.push(HashMap::new());
+        for (i, name) in params.names.iter().enumerate() {
+            if let Some(default) = &params.defaults[i] {
+                self.expression(default);
+            }
+            match &params.patterns[i] {
+                Some(pattern) => {
+                    for name in pattern.names() {
+                        self.declare(name, Binding::Variable);
+                    }
+                },
+                None => self.declare(name, Binding::Variable),
+            }
+        }
+        if let Some(rest) = &params.rest {
+            self.declare(rest, Binding::Variable);
+        }
+        for stmt in body {
+            self.statement(stmt);
+        }
+        self.scopes.pop();
+        self.functions.pop();
+        self.loops = loops;
+    }
+
+    fn expression(&mut self, expr: &Expr) {
+        match expr {
+            Expr::AnonFunction { params, body, source: _ } => self.function(params, body, false),
+            Expr::Assign { name, value } => {
+                self.expression(value);
+                self.assign(name);
+            },
+            Expr::CompoundAssign { target, operator: _, value } => {
+                self.target(target);
+                self.expression(value);
+            },
+            Expr::Update { target, operator: _, prefix: _ } => self.target(target),
+            Expr::Binary { left, operator: _, right } | Expr::Logical { left, operator: _, right } => {
+                self.expression(left);
+                self.expression(right);
+            },
+            Expr::Call { callee, paren: _, arguments, named } => {
+                self.expression(callee);
+                for arg in arguments {
+                    self.expression(arg);
+                }
+                for (_, arg) in named {
+                    self.expression(arg);
+                }
+            },
+            Expr::Conditional { condition, then, els } => {
+                self.expression(condition);
+                self.expression(then);
+                self.expression(els);
+            },
+            Expr::Get { object, name: _ } => self.expression(object),
+            Expr::Grouping { expression } => self.expression(expression),
+            Expr::Index { object, bracket: _, index } => {
+                self.expression(object);
+                self.expression(index);
+            },
+            Expr::Set { object, name: _, value } => {
+                self.expression(object);
+                self.expression(value);
+            },
+            Expr::IndexAssign { object, bracket: _, index, value } => {
+                self.expression(object);
+                self.expression(index);
+                self.expression(value);
+            },
+            Expr::List { elements } => {
+                for element in elements {
+                    self.expression(element);
+                }
+            },
+            Expr::Map { entries } => {
+                for (_, value) in entries {
+                    self.expression(value);
+                }
+            },
+            Expr::Literal { value: _ } => {},
+            Expr::Await { keyword, value } => {
+                // A generator can only stop at an await that is the whole
+                // statement, a variable's initializer or an assignment's
+                // value. Anywhere else it would have to stop mid-expression.
+                if self.functions.last() == Some(&true) && !self.suspending_await {
+                    self.errors.push(format!(
+                        "Line {}: in a generator, 'await' must be a whole statement, a variable's initializer or the value of an assignment",
+                        keyword.line_num
+                    ));
+                }
+                self.suspending_await = false;
+                self.expression(value);
+            },
+            Expr::Spawn { keyword: _, call } => self.expression(call),
+            Expr::Unary { operator: _, right } => self.expression(right),
+            Expr::Variable { name: _ } => {},
+        }
+    }
+
+    fn target(&mut self, target: &Expr) {
+        match target {
+            Expr::Variable { name } => self.assign(name),
+            other => self.expression(other),
+        }
+    }
+
+    // A match with a `_` arm or an unguarded binding always has somewhere to
+    // go. Without one, a match whose arms are all variants of an enum we can
+    // see must name every variant; guarded arms don't count as covering
+    // theirs. Other matches fail at runtime if no arm fits.
+    fn check_exhaustive(&mut self, keyword: &Token, arms: &[MatchArm]) {
+        if arms.iter().any(|arm| arm.is_catch_all()) {
+            return;
+        }
+
+        let mut enum_name: Option<&str> = None;
+        let mut covered = vec![];
+        for arm in arms {
+            let (name, variant) = match &arm.pattern {
+                Pattern::Value(Expr::Get { object, name: variant }) => match object.as_ref() {
+                    Expr::Variable { name } => (name, variant),
+                    _ => return,
+                },
+                _ => return,
+            };
+            if enum_name.is_some_and(|enum_name| enum_name != name.lexeme) {
+                return;
+            }
+            enum_name = Some(&name.lexeme);
+            if arm.guard.is_none() {
+                covered.push(variant.lexeme.as_str());
+            }
+        }
+
+        let name = match enum_name {
+            Some(name) => name,
+            None => return,
+        };
+        let variants = match self.lookup(name) {
+            Some(Binding::Enum(variants)) => variants,
+            _ => return,
+        };
+
+        for variant in &covered {
+            if !variants.iter().any(|v| v == variant) {
+                self.errors.push(format!("Line {}: enum '{}' has no variant '{}'", keyword.line_num, name, variant));
+                return;
+            }
+        }
+
+        let missing: Vec<String> = variants.iter()
+            .filter(|v| !covered.contains(&v.as_str()))
+            .map(|v| format!("{}.{}", name, v))
+            .collect();
+        if !missing.is_empty() {
+            self.errors.push(format!("Line {}: match is not exhaustive, missing {}", keyword.line_num, missing.join(", ")));
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<Binding> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .cloned()
+    }
+
+    fn declare(&mut self, name: &Token, binding: Binding) {
+        let scope = self.scopes.last_mut().expect("resolver always has a scope");
+        if scope.get(&name.lexeme).is_some_and(|b| *b != Binding::Variable) {
+            self.errors.push(format!("Line {}: cannot redeclare constant '{}'", name.line_num, name.lexeme));
+        }
+        scope.insert(name.lexeme.clone(), binding);
+    }
+
+    fn assign(&mut self, name: &Token) {
+        if self.lookup(&name.lexeme).is_some_and(|b| b != Binding::Variable) {
+            self.errors.push(format!("Line {}: cannot assign to constant '{}'", name.line_num, name.lexeme));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Resolver;
+    use crate::parser::Parser;
+    use crate::Scanner;
+
+    fn resolve(source: &str) -> Result<(), String> {
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens, source);
+        let stmts = parser.parse().unwrap();
+        Resolver::new().resolve(&stmts)
+    }
+
+    #[test]
+    fn rejects_assignment_to_visible_constant() {
+        assert_eq!(resolve("const A = 1; A = 2;"), Err("Line 1: cannot assign to constant 'A'".to_string()));
+        assert!(resolve("const A = 1; fun f() { A += 1; }").is_err());
+        assert!(resolve("const A = 1; A++;").is_err());
+        assert!(resolve("const A = 1; var A = 2;").is_err());
+    }
+
+    #[test]
+    fn allows_shadowing_and_later_declarations() {
+        assert!(resolve("const A = 1; { var A = 2; A = 3; }").is_ok());
+        assert!(resolve("const A = 1; fun f(A) { A = 2; }").is_ok());
+        // Declared after the function, so only the runtime check sees it.
+        assert!(resolve("fun f() { B = 2; } const B = 1;").is_ok());
+    }
+
+    #[test]
+    fn yield_only_inside_generators() {
+        assert!(resolve("fun* g() { yield 1; }").is_ok());
+        assert!(resolve("yield 1;").is_err());
+        assert!(resolve("fun* g() { fun f() { yield 1; } }").is_err());
+        assert!(resolve("fun* g() { var f = fun () { yield 1; }; }").is_err());
+    }
+
+    #[test]
+    fn jumps_only_inside_loops() {
+        assert!(resolve("while (true) { break; }").is_ok());
+        assert!(resolve("for (var i = 0; i < 3; i++) { if (i == 1) continue; }").is_ok());
+        assert!(resolve("do { match (1) { _ => break; } } while (true);").is_ok());
+        assert_eq!(resolve("break;"), Err("Line 1: 'break' outside a loop".to_string()));
+        assert!(resolve("while (true) { fun f() { continue; } }").is_err());
+    }
+
+    #[test]
+    fn generators_only_suspend_on_whole_awaits() {
+        assert!(resolve("fun* g(t) { await t; var x = await t; x = await t; var [a] = await t; }").is_ok());
+        assert!(resolve("fun* g(t) { print await t; }").is_err());
+        assert!(resolve("fun* g(t) { var x = 1 + await t; }").is_err());
+        // Outside generators an await just waits where it is.
+        assert!(resolve("fun f(t) { print 1 + await t; } print await spawn f(1);").is_ok());
+    }
+
+    #[test]
+    fn checks_enum_matches_for_missing_variants() {
+        let color = "enum Color { Red, Green, Blue } var c = Color.Red;";
+        assert_eq!(
+            resolve(&format!("{color} match (c) {{ Color.Red => print 1; }}")),
+            Err("Line 1: match is not exhaustive, missing Color.Green, Color.Blue".to_string())
+        );
+        assert!(resolve(&format!("{color} match (c) {{ Color.Red => print 1; _ => print 2; }}")).is_ok());
+        assert!(resolve(&format!("{color} match (c) {{ Color.Red => {{}} Color.Green => {{}} Color.Blue => {{}} }}")).is_ok());
+        assert!(resolve(&format!("{color} match (c) {{ Color.Pink => {{}} }}")).is_err());
+        assert!(resolve(&format!("{color} Color = 1;")).is_err());
+        assert!(resolve(&format!("{color} match (c) {{ Color.Red if true => {{}} Color.Green => {{}} Color.Blue => {{}} }}")).is_err());
+        assert!(resolve(&format!("{color} match (c) {{ Color.Red => {{}} other => {{}} }}")).is_ok());
+        // Literal matches are left to the runtime check.
+        assert!(resolve("match (1) { 1 => print 1; 2..5 => print 2; }").is_ok());
+    }
+}
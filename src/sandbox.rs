@@ -0,0 +1,218 @@
+use crate::expr::LiteralValue;
+use crate::native::{native_fn_requiring, CallContext};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// What scripts may touch outside the interpreter. Natives that reach the
+// file system, the environment, the clock or the process declare which
+// `Capability` they need when they are registered, and each call is checked
+// against these before the native runs; `import` checks reads too.
+// `Capabilities::none()`, the default, is for code you don't trust; the
+// command line runs with `all()`.
+#[derive(Clone, Debug, Default)]
+pub struct Capabilities {
+    // Directories whose files, at any depth, may be read or written.
+    pub read: Vec<PathBuf>,
+    pub write: Vec<PathBuf>,
+    pub env: bool,
+    pub clock: bool,
+    pub exit: bool,
+}
+
+impl Capabilities {
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    pub fn all() -> Self {
+        Self {
+            read: vec![PathBuf::from("/")],
+            write: vec![PathBuf::from("/")],
+            env: true,
+            clock: true,
+            exit: true,
+        }
+    }
+
+    pub fn can_read(&self, path: &Path) -> bool {
+        within(&self.read, path)
+    }
+
+    pub fn can_write(&self, path: &Path) -> bool {
+        within(&self.write, path)
+    }
+}
+
+// Something a native needs to be allowed. File access names the argument
+// holding the path, which is checked against the roots on every call.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Capability {
+    Read(usize),
+    Write(usize),
+    Env,
+    Clock,
+    Exit,
+}
+
+impl Capabilities {
+    // Whether `name` may be called with `args`, given it needs `capability`.
+    pub fn check(&self, capability: Capability, name: &str, args: &[LiteralValue]) -> Result<(), String> {
+        let denied = |what: String| Err(format!("{}: {} is not allowed", name, what));
+        let path = |index: usize| match args.get(index) {
+            Some(LiteralValue::StringValue(path)) => Ok(path.clone()),
+            other => Err(format!(
+                "argument {} of {}: expected a path, got {}",
+                index + 1,
+                name,
+                other.map_or("nothing", |value| value.to_type())
+            )),
+        };
+
+        match capability {
+            Capability::Read(index) => {
+                let path = path(index)?;
+                if self.can_read(Path::new(&path)) { Ok(()) } else { denied(format!("reading '{}'", path)) }
+            },
+            Capability::Write(index) => {
+                let path = path(index)?;
+                if self.can_write(Path::new(&path)) { Ok(()) } else { denied(format!("writing '{}'", path)) }
+            },
+            Capability::Env if !self.env => denied("reading environment variables".to_string()),
+            Capability::Clock if !self.clock => denied("reading the clock".to_string()),
+            Capability::Exit if !self.exit => denied("exiting the process".to_string()),
+            Capability::Env | Capability::Clock | Capability::Exit => Ok(()),
+        }
+    }
+}
+
+// Paths are compared once symlinks and `..` are resolved, so a script
+// can't climb out of a root. A file that doesn't exist yet is placed by
+// its directory.
+fn within(roots: &[PathBuf], path: &Path) -> bool {
+    let path = match fs::canonicalize(path) {
+        Ok(path) => path,
+        Err(_) => match (path.parent(), path.file_name()) {
+            (Some(dir), Some(name)) => match fs::canonicalize(if dir.as_os_str().is_empty() { Path::new(".") } else { dir }) {
+                Ok(dir) => dir.join(name),
+                Err(_) => return false,
+            },
+            _ => return false,
+        },
+    };
+
+    roots.iter().any(|root| {
+        let root = fs::canonicalize(root).unwrap_or_else(|_| root.clone());
+        path.starts_with(root)
+    })
+}
+
+fn clock_impl() -> String {
+    use std::time::SystemTime;
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    now.to_string()
+}
+
+fn read_file_impl(path: String) -> Result<String, String> {
+    fs::read_to_string(&path).map_err(|e| format!("read_file: cannot read '{}': {}", path, e))
+}
+
+fn write_file_impl(path: String, text: String) -> Result<(), String> {
+    fs::write(&path, text).map_err(|e| format!("write_file: cannot write '{}': {}", path, e))
+}
+
+// Nil when the variable isn't set.
+fn env_impl(name: String) -> Option<String> {
+    std::env::var(name).ok()
+}
+
+fn exit_impl(context: &CallContext, code: Option<f64>) {
+    context.flush();
+    std::process::exit(code.unwrap_or(0.0) as i32)
+}
+
+pub fn natives() -> Vec<(&'static str, LiteralValue)> {
+    vec![
+        ("clock", native_fn_requiring("clock", &[Capability::Clock], clock_impl)),
+        ("read_file", native_fn_requiring("read_file", &[Capability::Read(0)], read_file_impl)),
+        ("write_file", native_fn_requiring("write_file", &[Capability::Write(0)], write_file_impl)),
+        ("env", native_fn_requiring("env", &[Capability::Env], env_impl)),
+        ("exit", native_fn_requiring("exit", &[Capability::Exit], exit_impl)),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Capabilities, Capability};
+    use crate::lox::Lox;
+    use std::fs;
+
+    #[test]
+    fn untrusted_code_can_touch_nothing() {
+        let mut lox = Lox::new();
+        let mut error = |source: &str| lox.eval::<()>(source).unwrap_err().to_string();
+        assert_eq!(error("clock();"), "Line 1: clock: reading the clock is not allowed");
+        assert_eq!(error("read_file(\"Cargo.toml\");"), "Line 1: read_file: reading 'Cargo.toml' is not allowed");
+        assert_eq!(error("write_file(\"x.txt\", \"\");"), "Line 1: write_file: writing 'x.txt' is not allowed");
+        assert_eq!(error("env(\"HOME\");"), "Line 1: env: reading environment variables is not allowed");
+        assert_eq!(error("exit(1);"), "Line 1: exit: exiting the process is not allowed");
+        assert!(error("import \"./src/tests/cases/modules/lib/geometry.lox\" as g;").ends_with("is not allowed"));
+        // Whether the file exists doesn't show.
+        assert_eq!(error("import \"./missing.lox\" as m;"), "Line 1: cannot import './missing.lox': reading './missing.lox' is not allowed");
+    }
+
+    #[test]
+    fn files_only_under_their_roots() {
+        let root = std::env::temp_dir().join(format!("cii-sandbox-{}", std::process::id()));
+        fs::create_dir_all(root.join("data")).unwrap();
+        fs::write(root.join("secret.txt"), "hidden").unwrap();
+        fs::write(root.join("data/in.txt"), "hello").unwrap();
+
+        let mut lox = Lox::new();
+        lox.set_capabilities(Capabilities {
+            read: vec![root.join("data")],
+            write: vec![root.join("data")],
+            ..Capabilities::none()
+        });
+        lox.set("root", root.to_string_lossy().to_string()).unwrap();
+
+        let copied = lox.eval::<String>("write_file(root + \"/data/out.txt\", read_file(root + \"/data/in.txt\") + \"!\"); read_file(root + \"/data/out.txt\");");
+        let outside = lox.eval::<String>("read_file(root + \"/data/../secret.txt\");");
+        let escaped = lox.eval::<()>("write_file(root + \"/escaped.txt\", \"x\");");
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(copied.unwrap(), "hello!");
+        assert!(outside.unwrap_err().to_string().ends_with("secret.txt' is not allowed"));
+        assert!(escaped.unwrap_err().to_string().ends_with("escaped.txt' is not allowed"));
+    }
+
+    #[test]
+    fn the_rest_can_be_granted() {
+        let mut lox = Lox::new();
+        lox.set_capabilities(Capabilities { env: true, clock: true, ..Capabilities::none() });
+        assert!(lox.eval::<String>("clock();").is_ok());
+        assert_eq!(lox.eval::<Option<String>>("env(\"CII_SURELY_UNSET\");").unwrap(), None);
+        assert!(!lox.capabilities().can_read(std::path::Path::new(".")));
+    }
+
+    #[test]
+    fn registered_functions_declare_what_they_need() {
+        let mut lox = Lox::new();
+        lox.register_fn_requiring("user", &[Capability::Env], || "ada".to_string());
+        lox.register_fn_requiring("size", &[Capability::Read(0)], |path: String| fs::metadata(path).map(|m| m.len() as f64).map_err(|e| e.to_string()));
+        lox.register_fn("pure", || 1.0);
+
+        let mut error = |source: &str| lox.eval::<()>(source).unwrap_err().to_string();
+        assert_eq!(error("user();"), "Line 1: user: reading environment variables is not allowed");
+        assert_eq!(error("size(\"Cargo.toml\");"), "Line 1: size: reading 'Cargo.toml' is not allowed");
+        assert_eq!(error("size(1);"), "Line 1: argument 1 of size: expected a path, got Number");
+        assert_eq!(lox.eval::<f64>("pure();").unwrap(), 1.0);
+
+        lox.set_capabilities(Capabilities { env: true, read: vec![".".into()], ..Capabilities::none() });
+        assert_eq!(lox.eval::<String>("user();").unwrap(), "ada");
+        assert!(lox.eval::<f64>("size(\"Cargo.toml\");").unwrap() > 0.0);
+    }
+}
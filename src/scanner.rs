@@ -4,14 +4,14 @@ fn is_digit(ch: char) -> bool {
     return ch as u8 >= '0' as u8 && ch as u8 <= '9' as u8;
 }
 
-fn is_alpha(ch: char) -> bool {
+pub fn is_alpha(ch: char) -> bool {
     let uch = ch as u8;
     (uch >= 'a' as u8 && uch <= 'z' as u8) || 
         (uch >= 'A' as u8 && uch <= 'Z' as u8) ||
         (uch == '_' as u8)
 }
 
-fn is_alpha_numeric(ch: char) -> bool {
+pub fn is_alpha_numeric(ch: char) -> bool {
     if is_digit(ch) || is_alpha(ch) {
         return true;
     }
@@ -25,7 +25,11 @@ fn get_keywords_hashmap() -> HashMap<&'static str, TokenType> {
         ("or", TokenType::Or),
         ("print", TokenType::Print),
         ("var", TokenType::Var),
+        ("const", TokenType::Const),
         ("while", TokenType::While),
+        ("do", TokenType::Do),
+        ("break", TokenType::Break),
+        ("continue", TokenType::Continue),
         ("true", TokenType::True),
         ("false", TokenType::False),
         ("fun", TokenType::Fun),
@@ -37,6 +41,16 @@ fn get_keywords_hashmap() -> HashMap<&'static str, TokenType> {
         ("this", TokenType::This),
         ("class", TokenType::Class),
         ("else", TokenType::Else),
+        ("throw", TokenType::Throw),
+        ("try", TokenType::Try),
+        ("catch", TokenType::Catch),
+        ("finally", TokenType::Finally),
+        ("import", TokenType::Import),
+        ("enum", TokenType::Enum),
+        ("match", TokenType::Match),
+        ("yield", TokenType::Yield),
+        ("await", TokenType::Await),
+        ("spawn", TokenType::Spawn),
     ])
 }
 
@@ -61,6 +75,13 @@ impl Scanner {
         }
     }
 
+    // Counts lines from `line` rather than 1, for source cut out of a
+    // longer text.
+    pub fn starting_at_line(mut self, line: usize) -> Self {
+        self.line = line;
+        self
+    }
+
     pub fn scan_tokens(&mut self) -> Result<Vec<Token>, String> {
         let mut errors = vec![];
         while !self.is_at_end() {
@@ -100,11 +121,74 @@ impl Scanner {
             '{' => self.add_token(TokenType::LeftBrace),
             '}' => self.add_token(TokenType::RightBrace),
             ',' => self.add_token(TokenType::Comma),
-            '.' => self.add_token(TokenType::Dot),
-            '-' => self.add_token(TokenType::Minus),
-            '+' => self.add_token(TokenType::Plus),
+            '.' => {
+                if self.peek() == '.' && self.peek_next() == '.' {
+                    self.advance();
+                    self.advance();
+                    self.add_token(TokenType::Ellipsis);
+                }else if self.char_match('.') {
+                    let token = if self.char_match('=') {
+                        TokenType::DotDotEqual
+                    }else {
+                        TokenType::DotDot
+                    };
+                    self.add_token(token);
+                }else {
+                    self.add_token(TokenType::Dot);
+                }
+            },
+            '[' => self.add_token(TokenType::LeftBracket),
+            ']' => self.add_token(TokenType::RightBracket),
+            '-' => {
+                let token = if self.char_match('=') {
+                    TokenType::MinusEqual
+                }else if self.char_match('-') {
+                    TokenType::MinusMinus
+                }else {
+                    TokenType::Minus
+                };
+                self.add_token(token);
+            },
+            '+' => {
+                let token = if self.char_match('=') {
+                    TokenType::PlusEqual
+                }else if self.char_match('+') {
+                    TokenType::PlusPlus
+                }else {
+                    TokenType::Plus
+                };
+                self.add_token(token);
+            },
             ';' => self.add_token(TokenType::SemiColon),
-            '*' => self.add_token(TokenType::Star),
+            '*' => {
+                let token = if self.char_match('=') {
+                    TokenType::StarEqual
+                }else {
+                    TokenType::Star
+                };
+                self.add_token(token);
+            },
+            '%' => {
+                let token = if self.char_match('=') {
+                    TokenType::PercentEqual
+                }else {
+                    TokenType::Percent
+                };
+                self.add_token(token);
+            },
+            '&' => self.add_token(TokenType::Ampersand),
+            '|' => self.add_token(TokenType::Pipe),
+            '^' => self.add_token(TokenType::Caret),
+            '~' => self.add_token(TokenType::Tilde),
+            ':' => self.add_token(TokenType::Colon),
+            '?' => {
+                let token = if self.char_match('?') {
+                    TokenType::QuestionQuestion
+                }else {
+                    TokenType::Question
+                };
+                self.add_token(token);
+            },
             '!' => {
                 let token = if self.char_match('=') {
                     TokenType::BangEqual
@@ -116,6 +200,8 @@ impl Scanner {
             '=' =>  {
                 let token = if self.char_match('=') {
                     TokenType::EqualEqual
+                }else if self.char_match('>') {
+                    TokenType::Arrow
                 }else {
                     TokenType::Equal
                 };
@@ -124,6 +210,8 @@ impl Scanner {
             '<' => {
                 let token = if self.char_match('=') {
                     TokenType::LessEqual
+                }else if self.char_match('<') {
+                    TokenType::LessLess
                 }else {
                     TokenType::Less
                 };
@@ -134,6 +222,8 @@ impl Scanner {
             '>' => {
                 let token = if self.char_match('=') {
                     TokenType::GreaterEqual
+                }else if self.char_match('>') {
+                    TokenType::GreaterGreater
                 }else {
                     TokenType::Greater
                 };
@@ -148,6 +238,8 @@ impl Scanner {
                         }
                         self.advance();
                     }
+                }else if self.char_match('=') {
+                    self.add_token(TokenType::SlashEqual);
                 }else {
                     self.add_token(TokenType::Slash);
                 }
@@ -256,12 +348,14 @@ impl Scanner {
     fn add_token_lit(&mut self, token_type: TokenType, literal: Option<LiteralValue>) {
         let text = self.source[self.start .. self.current].to_string();
 
-        self.tokens.push(Token::new(
+        let mut token = Token::new(
             token_type,
             text,
             literal,
             self.line,
-        ));
+        );
+        token.offset = self.start;
+        self.tokens.push(token);
     }
     
     fn advance(&mut self) -> char {
@@ -299,6 +393,8 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
     Dot,
     Minus,
@@ -306,6 +402,13 @@ pub enum TokenType {
     SemiColon,
     Slash,
     Star,
+    Percent,
+    Ampersand,
+    Pipe,
+    Caret,
+    Tilde,
+    Colon,
+    Question,
 
     // One or two chars
     Bang,
@@ -316,6 +419,20 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
+    LessLess,
+    GreaterGreater,
+    PlusEqual,
+    MinusEqual,
+    StarEqual,
+    SlashEqual,
+    PercentEqual,
+    PlusPlus,
+    MinusMinus,
+    QuestionQuestion,
+    Arrow,
+    Ellipsis,
+    DotDot,
+    DotDotEqual,
 
     // Literals
     Identifier,
@@ -338,7 +455,21 @@ pub enum TokenType {
     This,
     True,
     Var,
+    Const,
     While,
+    Do,
+    Break,
+    Continue,
+    Throw,
+    Try,
+    Catch,
+    Finally,
+    Import,
+    Enum,
+    Match,
+    Yield,
+    Await,
+    Spawn,
 
 
     Eof,
@@ -363,6 +494,9 @@ pub struct Token {
     pub lexeme: String,
     pub literal: Option<LiteralValue>,
     pub line_num: usize,
+    // Byte offset of the lexeme in the scanned source; 0 for tokens the
+    // parser makes up.
+    pub offset: usize,
 }
 
 impl Token {
@@ -372,6 +506,7 @@ impl Token {
             lexeme,
             literal,
             line_num,
+            offset: 0,
         }
     }
 
@@ -388,7 +523,7 @@ impl Token {
 mod tests {
     use crate::Scanner;
     use crate::TokenType;
-    use crate::LiteralValue;
+    use crate::scanner::LiteralValue;
 
     #[test]
     fn handle_one_char_token() {
@@ -420,6 +555,93 @@ mod tests {
         assert_eq!(scanner.tokens[4].token_type, TokenType::Eof);
     }
 
+    #[test]
+    fn handle_bitwise_tokens() {
+        let source = "& | ^ ~ << >> < >";
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().unwrap();
+
+        assert_eq!(scanner.tokens.len(), 9);
+        assert_eq!(scanner.tokens[0].token_type, TokenType::Ampersand);
+        assert_eq!(scanner.tokens[1].token_type, TokenType::Pipe);
+        assert_eq!(scanner.tokens[2].token_type, TokenType::Caret);
+        assert_eq!(scanner.tokens[3].token_type, TokenType::Tilde);
+        assert_eq!(scanner.tokens[4].token_type, TokenType::LessLess);
+        assert_eq!(scanner.tokens[5].token_type, TokenType::GreaterGreater);
+        assert_eq!(scanner.tokens[6].token_type, TokenType::Less);
+        assert_eq!(scanner.tokens[7].token_type, TokenType::Greater);
+        assert_eq!(scanner.tokens[8].token_type, TokenType::Eof);
+    }
+
+    #[test]
+    fn handle_compound_assignment_tokens() {
+        let source = "+= -= *= /= %= ++ -- % // comment";
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().unwrap();
+
+        assert_eq!(scanner.tokens.len(), 9);
+        assert_eq!(scanner.tokens[0].token_type, TokenType::PlusEqual);
+        assert_eq!(scanner.tokens[1].token_type, TokenType::MinusEqual);
+        assert_eq!(scanner.tokens[2].token_type, TokenType::StarEqual);
+        assert_eq!(scanner.tokens[3].token_type, TokenType::SlashEqual);
+        assert_eq!(scanner.tokens[4].token_type, TokenType::PercentEqual);
+        assert_eq!(scanner.tokens[5].token_type, TokenType::PlusPlus);
+        assert_eq!(scanner.tokens[6].token_type, TokenType::MinusMinus);
+        assert_eq!(scanner.tokens[7].token_type, TokenType::Percent);
+        assert_eq!(scanner.tokens[8].token_type, TokenType::Eof);
+    }
+
+    #[test]
+    fn handle_conditional_tokens() {
+        let source = "a ? b : c ?? d";
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().unwrap();
+
+        assert_eq!(scanner.tokens.len(), 8);
+        assert_eq!(scanner.tokens[1].token_type, TokenType::Question);
+        assert_eq!(scanner.tokens[3].token_type, TokenType::Colon);
+        assert_eq!(scanner.tokens[5].token_type, TokenType::QuestionQuestion);
+    }
+
+    #[test]
+    fn handle_arrow_token() {
+        let source = "(a) => a == b >= c";
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().unwrap();
+
+        assert_eq!(scanner.tokens.len(), 10);
+        assert_eq!(scanner.tokens[3].token_type, TokenType::Arrow);
+        assert_eq!(scanner.tokens[5].token_type, TokenType::EqualEqual);
+        assert_eq!(scanner.tokens[7].token_type, TokenType::GreaterEqual);
+    }
+
+    #[test]
+    fn handle_brackets_and_ellipsis() {
+        let source = "[...rest].";
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().unwrap();
+
+        assert_eq!(scanner.tokens.len(), 6);
+        assert_eq!(scanner.tokens[0].token_type, TokenType::LeftBracket);
+        assert_eq!(scanner.tokens[1].token_type, TokenType::Ellipsis);
+        assert_eq!(scanner.tokens[2].token_type, TokenType::Identifier);
+        assert_eq!(scanner.tokens[3].token_type, TokenType::RightBracket);
+        assert_eq!(scanner.tokens[4].token_type, TokenType::Dot);
+    }
+
+    #[test]
+    fn handle_ranges() {
+        let source = "1..5 2..=3";
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().unwrap();
+
+        assert_eq!(scanner.tokens.len(), 7);
+        assert_eq!(scanner.tokens[0].token_type, TokenType::Number);
+        assert_eq!(scanner.tokens[1].token_type, TokenType::DotDot);
+        assert_eq!(scanner.tokens[2].token_type, TokenType::Number);
+        assert_eq!(scanner.tokens[4].token_type, TokenType::DotDotEqual);
+    }
+
     #[test]
     fn handle_string_literal() {
         let source = r#""ABC""#; // escape sequence comes in between while parsing.
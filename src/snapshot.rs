@@ -0,0 +1,400 @@
+use crate::environment::Environment;
+use crate::error::LoxError;
+use crate::expr::{EnumType, Expr, LiteralValue};
+use crate::interpreter::{Interpreter, LoxFunction};
+use crate::parser::Parser;
+use crate::resolver::Resolver;
+use crate::scanner::Scanner;
+use crate::scanner::TokenType::{Fun, LeftParen};
+use crate::stmt::Stmt;
+use serde_json::{json, Map, Number, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+// A session's globals saved to a JSON file and read back later:
+//
+//     {"version": 1, "globals": [{"name": "x", "constant": false, "value": 1}]}
+//
+// Nil, booleans, numbers and strings are stored as themselves, anything
+// else as an object with one key saying what it is. Functions are stored
+// by their source and the line it starts on, and declared again on
+// restore, so only those whose closure is the global scope can be saved. A list or map reachable from
+// two globals comes back as two copies.
+
+const VERSION: u64 = 1;
+
+pub fn save(globals: &Rc<RefCell<Environment>>, path: &Path) -> Result<(), LoxError> {
+    let builtins: Vec<&str> = Interpreter::natives().iter().map(|(name, _)| *name).collect();
+    let environment = globals.borrow();
+    let mut entries: Vec<(&String, &LiteralValue)> = environment.entries().collect();
+    entries.sort_by_key(|(name, _)| *name);
+
+    let mut saved = vec![];
+    for (name, value) in entries {
+        let builtin = matches!(value, LiteralValue::Callable { name: native, arity: _, fun: _, function: None } if native == name);
+        if builtin && builtins.contains(&name.as_str()) {
+            continue;
+        }
+
+        let value = encode(value, globals, &mut vec![]).map_err(|e| LoxError::Host(format!("cannot save '{}': {}", name, e)))?;
+        saved.push(json!({
+            "name": name,
+            "constant": environment.declares_constant(name),
+            "value": value,
+        }));
+    }
+
+    let snapshot = json!({ "version": VERSION, "globals": saved });
+    let text = serde_json::to_string_pretty(&snapshot).map_err(|e| LoxError::Host(e.to_string()))?;
+    fs::write(path, text).map_err(|e| LoxError::Io(format!("cannot write '{}': {}", path.display(), e)))
+}
+
+// `parents` are the lists and maps being encoded around this value, to
+// catch one that contains itself.
+fn encode(value: &LiteralValue, globals: &Rc<RefCell<Environment>>, parents: &mut Vec<usize>) -> Result<Value, String> {
+    match value {
+        LiteralValue::Nil => Ok(Value::Null),
+        LiteralValue::True => Ok(Value::Bool(true)),
+        LiteralValue::False => Ok(Value::Bool(false)),
+        LiteralValue::Number(x) => match Number::from_f64(*x as f64) {
+            Some(n) => Ok(Value::Number(n)),
+            None => Err(format!("{} can't be saved", x)),
+        },
+        LiteralValue::StringValue(s) => Ok(Value::String(s.clone())),
+        LiteralValue::List(items) => {
+            let address = Rc::as_ptr(items) as usize;
+            if parents.contains(&address) {
+                return Err("a list that contains itself can't be saved".to_string());
+            }
+            parents.push(address);
+            let items: Result<Vec<Value>, String> = items.borrow().iter().map(|item| encode(item, globals, parents)).collect();
+            parents.pop();
+            Ok(json!({ "list": items? }))
+        },
+        LiteralValue::Map(entries) => {
+            let address = Rc::as_ptr(entries) as usize;
+            if parents.contains(&address) {
+                return Err("a map that contains itself can't be saved".to_string());
+            }
+            parents.push(address);
+            let mut object = Map::new();
+            for (key, value) in entries.borrow().iter() {
+                object.insert(key.clone(), encode(value, globals, parents)?);
+            }
+            parents.pop();
+            Ok(json!({ "map": object }))
+        },
+        LiteralValue::Error { message, line } => Ok(json!({ "error": { "message": message, "line": line } })),
+        LiteralValue::Enum(enum_type) => Ok(json!({ "enum": { "name": enum_type.name, "variants": enum_type.variants } })),
+        LiteralValue::EnumValue { enum_type, index } => Ok(json!({
+            "enum_value": { "name": enum_type.name, "variants": enum_type.variants, "index": index },
+        })),
+        LiteralValue::Callable { name, arity: _, fun: _, function } => match function {
+            Some(function) if closes_over_globals(function, globals) => {
+                let source = function.source();
+                Ok(json!({ "function": { "source": source.text, "line": source.line } }))
+            },
+            Some(_) => Err(format!("function {} closes over local variables", name)),
+            None => Err(format!("native function {} can't be saved", name)),
+        },
+        LiteralValue::Namespace { name, environment: _ } => Err(format!("module {} can't be saved, import it again instead", name)),
+        LiteralValue::HostObject(object) => Err(format!("{} is a host object and can't be saved", object.type_name())),
+        other => Err(format!("{} values can't be saved", other.to_type())),
+    }
+}
+
+// Either declared at the top level, or restored into a scope of its own
+// just inside it, which holds nothing but the function.
+fn closes_over_globals(function: &LoxFunction, globals: &Rc<RefCell<Environment>>) -> bool {
+    let closure = function.closure();
+    if Rc::ptr_eq(closure, globals) {
+        return true;
+    }
+
+    let scope = closure.borrow();
+    let only_itself = scope.entries().all(|(name, _)| name == function.name());
+    only_itself && scope.enclosing.as_ref().is_some_and(|enclosing| Rc::ptr_eq(enclosing, globals))
+}
+
+pub fn restore(globals: &Rc<RefCell<Environment>>, path: &Path) -> Result<(), LoxError> {
+    let text = fs::read_to_string(path).map_err(|e| LoxError::Io(format!("cannot read '{}': {}", path.display(), e)))?;
+    let invalid = |message: String| LoxError::Host(format!("invalid snapshot '{}': {}", path.display(), message));
+    let snapshot: Value = serde_json::from_str(&text).map_err(|e| invalid(e.to_string()))?;
+    if snapshot["version"] != json!(VERSION) {
+        return Err(invalid(format!("unsupported version {}", snapshot["version"])));
+    }
+    let saved = snapshot["globals"].as_array().ok_or_else(|| invalid("missing globals".to_string()))?;
+
+    // Every global is decoded, functions included, before any is defined,
+    // so a bad one leaves the session as it was.
+    let mut restorer = Restorer {
+        globals: globals.clone(),
+        enums: HashMap::new(),
+    };
+    let mut restored = vec![];
+    for global in saved {
+        let name = global["name"].as_str().ok_or_else(|| invalid("global without a name".to_string()))?;
+        if globals.borrow().declares_constant(name) {
+            return Err(LoxError::Host(format!("cannot restore '{}': it is already a constant", name)));
+        }
+        let value = restorer.global(&global["value"], name).map_err(|e| invalid(format!("global '{}': {}", name, e)))?;
+        restored.push((name, global["constant"].as_bool() == Some(true), value));
+    }
+
+    let mut globals = globals.borrow_mut();
+    for (name, constant, value) in restored {
+        match constant {
+            true => globals.define_constant(name.to_string(), value),
+            false => globals.define(name.to_string(), value),
+        }
+    }
+    Ok(())
+}
+
+struct Restorer {
+    globals: Rc<RefCell<Environment>>,
+    // Enums by name and variants, so their values come back equal to them.
+    enums: HashMap<(String, Vec<String>), Rc<EnumType>>,
+}
+
+impl Restorer {
+    // A function saved under its own name closes over the globals again,
+    // so it finds itself there once it is defined.
+    fn global(&mut self, value: &Value, name: &str) -> Result<LiteralValue, String> {
+        if let Some(Value::Object(function)) = value.as_object().filter(|object| object.len() == 1).and_then(|object| object.get("function")) {
+            if let [Stmt::Function { name: declared, params, body, generator, source }] = parse_function(function)?.as_slice() {
+                if declared.lexeme == name {
+                    return Ok(LoxFunction::declared(declared, params, body, *generator, source, self.globals.clone()));
+                }
+            }
+        }
+        self.decode(value)
+    }
+
+    fn decode(&mut self, value: &Value) -> Result<LiteralValue, String> {
+        let object = match value {
+            Value::Null => return Ok(LiteralValue::Nil),
+            Value::Bool(b) => return Ok(LiteralValue::from_bool(*b)),
+            Value::Number(n) => return Ok(LiteralValue::Number(n.as_f64().unwrap_or(f64::NAN) as f32)),
+            Value::String(s) => return Ok(LiteralValue::StringValue(s.clone())),
+            Value::Array(_) => return Err("unexpected array".to_string()),
+            Value::Object(object) if object.len() == 1 => object,
+            Value::Object(_) => return Err("expected an object with one key".to_string()),
+        };
+
+        let (kind, value) = object.iter().next().expect("checked to have one key");
+        match (kind.as_str(), value) {
+            ("list", Value::Array(items)) => {
+                let items: Result<Vec<LiteralValue>, String> = items.iter().map(|item| self.decode(item)).collect();
+                Ok(LiteralValue::list(items?))
+            },
+            ("map", Value::Object(entries)) => {
+                let mut values = vec![];
+                for (key, value) in entries {
+                    values.push((key.clone(), self.decode(value)?));
+                }
+                Ok(LiteralValue::map(values))
+            },
+            ("error", Value::Object(error)) => Ok(LiteralValue::Error {
+                message: error["message"].as_str().unwrap_or_default().to_string(),
+                line: error["line"].as_u64().unwrap_or(0) as usize,
+            }),
+            ("enum", Value::Object(enum_type)) => Ok(LiteralValue::Enum(self.enum_type(enum_type)?)),
+            ("enum_value", Value::Object(enum_value)) => {
+                let enum_type = self.enum_type(enum_value)?;
+                match enum_value["index"].as_u64() {
+                    Some(index) if (index as usize) < enum_type.variants.len() => Ok(enum_type.value(index as usize)),
+                    _ => Err(format!("bad variant index for enum {}", enum_type.name)),
+                }
+            },
+            ("function", Value::Object(function)) => self.function(function),
+            (kind, _) => Err(format!("unknown value '{}'", kind)),
+        }
+    }
+
+    fn enum_type(&mut self, value: &Map<String, Value>) -> Result<Rc<EnumType>, String> {
+        let name = value["name"].as_str().ok_or("enum without a name")?.to_string();
+        let variants: Option<Vec<String>> = value["variants"].as_array()
+            .map(|variants| variants.iter().filter_map(|v| v.as_str().map(|v| v.to_string())).collect());
+        let variants = variants.ok_or(format!("enum {} without variants", name))?;
+
+        let enum_type = self.enums.entry((name.clone(), variants.clone())).or_insert_with(|| Rc::new(EnumType { name, variants }));
+        Ok(enum_type.clone())
+    }
+
+    // Declared in a scope of its own, since declaring it globally could
+    // overwrite another global.
+    fn function(&mut self, function: &Map<String, Value>) -> Result<LiteralValue, String> {
+        match parse_function(function)?.as_slice() {
+            [Stmt::Expression { expression }] => expression.evaluate(self.globals.clone()).map_err(|e| e.to_string()),
+            [Stmt::Function { name, params, body, generator, source }] => {
+                let scope = Environment::new_enclosed(&self.globals);
+                let value = LoxFunction::declared(name, params, body, *generator, source, scope.clone());
+                scope.borrow_mut().define(name.lexeme.clone(), value.clone());
+                Ok(value)
+            },
+            _ => unreachable!("parse_function only returns functions"),
+        }
+    }
+}
+
+// Parses the saved source of a function: a declaration, or a lambda or
+// arrow function. Anything else is refused rather than run, since a
+// snapshot is only a file and may have been edited.
+fn parse_function(function: &Map<String, Value>) -> Result<Vec<Stmt>, String> {
+    let text = function.get("source").and_then(|source| source.as_str()).ok_or("function without a source")?;
+    let line = function.get("line").and_then(|line| line.as_u64()).ok_or("function without a line")? as usize;
+    let mut source = text.to_string();
+
+    // Lambdas and arrow functions are expressions and need a `;` to
+    // make a statement.
+    let mut tokens = Scanner::new(&source).starting_at_line(line).scan_tokens()?;
+    let declaration = matches!(tokens.as_slice(), [fun, next, ..] if fun.token_type == Fun && next.token_type != LeftParen);
+    if !declaration {
+        source.push(';');
+        tokens = Scanner::new(&source).starting_at_line(line).scan_tokens()?;
+    }
+    let stmts = Parser::new(tokens, &source).parse()?;
+
+    match stmts.as_slice() {
+        [Stmt::Function { .. }] | [Stmt::Expression { expression: Expr::AnonFunction { .. } }] => {},
+        _ => return Err("expected a function".to_string()),
+    }
+    Resolver::new().resolve(&stmts)?;
+    Ok(stmts)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lox::Lox;
+    use crate::output::OutputBuffer;
+    use std::path::PathBuf;
+    use std::rc::Rc;
+
+    fn snapshot_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("cii-snapshot-{}-{}.json", name, std::process::id()))
+    }
+
+    #[test]
+    fn sessions_round_trip() {
+        let path = snapshot_path("round-trip");
+        let mut session = Lox::new();
+        session.eval::<()>("
+            const LIMIT = 3;
+            var scores = {ada: [1, 2], \"grace h\": nil};
+            enum Level { Low, High }
+            var level = Level.High;
+            fun fib(n) {
+                n < 2 ? n : fib(n - 1) + fib(n - 2);
+            }
+            var twice = (x) => x * 2;
+            var helpers = [fib, fun (s) { s + \"!\"; }];
+            var failure;
+            try { throw nil + 1; } catch (e) { failure = e; }
+            fun broken() {
+
+                nil - 1;
+            }
+        ").unwrap();
+        session.save_snapshot(&path).unwrap();
+
+        let output = OutputBuffer::new();
+        let mut restored = Lox::new();
+        restored.set_stdout(output.clone());
+        restored.restore_snapshot(&path).unwrap();
+        let result = restored.eval::<String>("
+            print scores;
+            print level == Level.High;
+            print helpers[0](10) + twice(LIMIT);
+            print failure;
+            helpers[1](\"ok\");
+        ");
+        assert_eq!(result.unwrap(), "ok!");
+        assert_eq!(output.contents(), "{ada: [1, 2], \"grace h\": nil}\ntrue\n61\nError at line 12: Plus is not implemented for operands nil and 1\n");
+        assert_eq!(restored.eval::<()>("LIMIT = 4;").unwrap_err().to_string(), "Line 1: cannot assign to constant 'LIMIT'");
+        // Restored functions report the lines they were written on.
+        assert_eq!(restored.eval::<()>("broken();").unwrap_err().to_string(), "Line 15: Minus is not implemented for operands nil and 1");
+
+        // What was restored can be saved again.
+        restored.save_snapshot(&path).unwrap();
+        let mut again = Lox::new();
+        again.restore_snapshot(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(again.eval::<f64>("helpers[0](7) + fib(7);").unwrap(), 26.0);
+    }
+
+    #[test]
+    fn values_that_cannot_be_saved() {
+        let path = snapshot_path("errors");
+        let error = |source: &str| {
+            let mut lox = Lox::new();
+            lox.eval::<()>(source).unwrap();
+            lox.save_snapshot(&path).unwrap_err().to_string()
+        };
+        assert_eq!(error("fun counter() { var n = 0; fun inc() { n += 1; } inc; } var c = counter();"), "cannot save 'c': function inc closes over local variables");
+        assert_eq!(error("var l = [1]; l[0] = l;"), "cannot save 'l': a list that contains itself can't be saved");
+        assert_eq!(error("var f = clock;"), "cannot save 'f': native function clock can't be saved");
+        assert_eq!(error("fun* g() { yield 1; } var gen = g();"), "cannot save 'gen': Generator values can't be saved");
+
+        let mut lox = Lox::new();
+        lox.register_fn("double", |x: f64| x * 2.0);
+        assert_eq!(lox.save_snapshot(&path).unwrap_err().to_string(), "cannot save 'double': native function double can't be saved");
+
+        let mut lox = Lox::new();
+        lox.set("probe", Rc::new(Probe)).unwrap();
+        assert_eq!(lox.save_snapshot(&path).unwrap_err().to_string(), "cannot save 'probe': Probe is a host object and can't be saved");
+        assert!(!path.exists());
+    }
+
+    struct Probe;
+
+    impl crate::host::HostObject for Probe {
+        fn type_name(&self) -> &str {
+            "Probe"
+        }
+    }
+
+    #[test]
+    fn rejects_bad_snapshots() {
+        let path = snapshot_path("bad");
+        let mut lox = Lox::new();
+        std::fs::write(&path, "{\"version\": 2, \"globals\": []}").unwrap();
+        let unsupported = lox.restore_snapshot(&path).unwrap_err().to_string();
+        std::fs::write(&path, "{\"version\": 1, \"globals\": [{\"name\": \"x\", \"value\": {\"function\": {\"source\": \"1 +\", \"line\": 1}}}]}").unwrap();
+        let broken = lox.restore_snapshot(&path).unwrap_err().to_string();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(unsupported.ends_with("unsupported version 2"));
+        assert!(broken.contains("global 'x': "));
+        assert!(lox.restore_snapshot(&path).is_err());
+    }
+
+    #[test]
+    fn only_functions_are_run_and_only_when_all_is_well() {
+        let path = snapshot_path("code");
+        let output = OutputBuffer::new();
+        let mut lox = Lox::new();
+        lox.set_stdout(output.clone());
+        lox.eval::<()>("var kept = 1;").unwrap();
+
+        let mut restore = |value: &str| {
+            let globals = format!("[{{\"name\": \"kept\", \"value\": 2}}, {{\"name\": \"x\", \"value\": {{\"function\": {{\"source\": {}, \"line\": 1}}}}}}]", value);
+            std::fs::write(&path, format!("{{\"version\": 1, \"globals\": {}}}", globals)).unwrap();
+            lox.restore_snapshot(&path).unwrap_err().to_string()
+        };
+        let sum = restore("\"1 + 2\"");
+        let call = restore("\"print \\\"ran\\\"\"");
+        let trailing = restore("\"fun x() {} print \\\"ran\\\";\"");
+        let invoked = restore("\"fun () { print \\\"ran\\\"; }()\"");
+        std::fs::remove_file(&path).unwrap();
+
+        for error in [sum, call, trailing, invoked] {
+            assert!(error.ends_with("global 'x': expected a function"), "{}", error);
+        }
+        assert_eq!(output.contents(), "");
+        assert_eq!(lox.get::<f64>("kept").unwrap(), 1.0);
+    }
+}
@@ -1,24 +1,189 @@
-use crate::expr::Expr;
+use crate::expr::{Arity, Expr};
 use crate::scanner::Token;
+use std::rc::Rc;
+
+// A function's parameter list. `defaults` runs parallel to `names`; once a
+// parameter has a default every later one does too. `rest` collects any
+// remaining arguments into a list. A parameter written as a pattern, like
+// `[x, y]`, has it in `patterns` and a name that can't be passed by name.
+#[derive(Debug, Clone)]
+pub struct Parameters {
+    pub names: Vec<Token>,
+    pub defaults: Vec<Option<Expr>>,
+    pub patterns: Vec<Option<BindingPattern>>,
+    pub rest: Option<Token>,
+}
+
+// The left side of a declaration, a parameter or a for-in loop variable.
+#[derive(Debug, Clone)]
+pub enum BindingPattern {
+    Name(Token),
+    // [a, b, ...rest], nesting allowed
+    List {
+        bracket: Token,
+        elements: Vec<BindingPattern>,
+        rest: Option<Token>,
+    },
+    // {name, age} reads those properties off the value
+    Object {
+        brace: Token,
+        names: Vec<Token>,
+    },
+}
+
+impl BindingPattern {
+    pub fn names(&self) -> Vec<&Token> {
+        match self {
+            BindingPattern::Name(name) => vec![name],
+            BindingPattern::List { bracket: _, elements, rest } => {
+                let mut names: Vec<&Token> = elements.iter().flat_map(|e| e.names()).collect();
+                names.extend(rest);
+                names
+            },
+            BindingPattern::Object { brace: _, names } => names.iter().collect(),
+        }
+    }
+
+    pub fn line(&self) -> usize {
+        match self {
+            BindingPattern::Name(name) => name.line_num,
+            BindingPattern::List { bracket, elements: _, rest: _ } => bracket.line_num,
+            BindingPattern::Object { brace, names: _ } => brace.line_num,
+        }
+    }
+
+    pub fn to_string(&self) -> String {
+        match self {
+            BindingPattern::Name(name) => name.lexeme.clone(),
+            BindingPattern::List { bracket: _, elements, rest } => {
+                let mut parts: Vec<String> = elements.iter().map(|e| e.to_string()).collect();
+                if let Some(rest) = rest {
+                    parts.push(format!("...{}", rest.lexeme));
+                }
+                format!("[{}]", parts.join(", "))
+            },
+            BindingPattern::Object { brace: _, names } => {
+                let names: Vec<&str> = names.iter().map(|n| n.lexeme.as_str()).collect();
+                format!("{{{}}}", names.join(", "))
+            },
+        }
+    }
+}
+
+impl Parameters {
+    pub fn arity(&self) -> Arity {
+        let min = self.defaults.iter().take_while(|d| d.is_none()).count();
+        let max = match self.rest {
+            Some(_) => None,
+            None => Some(self.names.len()),
+        };
+
+        Arity { min, max }
+    }
+}
+
+// A pattern on the left of a match arm.
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    // `_` matches anything.
+    Wildcard,
+    // A literal or an enum variant such as `Color.Red`, compared with `==`.
+    Value(Expr),
+    // `lo..hi` leaves `hi` out, `lo..=hi` includes it.
+    Range { start: Expr, end: Expr, inclusive: bool },
+    // A bare name matches anything and binds the subject to it for the arm.
+    Binding(Token),
+}
+
+// `pattern if guard => body`. The guard and the body see the arm's binding.
+#[derive(Debug, Clone)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub guard: Option<Expr>,
+    pub body: Box<Stmt>,
+}
+
+impl MatchArm {
+    // Matches every subject, so later arms are never reached.
+    pub fn is_catch_all(&self) -> bool {
+        self.guard.is_none() && matches!(self.pattern, Pattern::Wildcard | Pattern::Binding(_))
+    }
+}
+
+// The text of a function exactly as written, and the line it starts on, so
+// a saved function keeps its line numbers when it is declared again.
+#[derive(Debug)]
+pub struct FunctionSource {
+    pub text: String,
+    pub line: usize,
+}
 
 #[derive(Debug, Clone)]
 pub enum Stmt {
     Expression { expression: Expr },
     Print { expression: Expr },
     Var { name: Token, initializer: Expr },
+    Destructure { pattern: BindingPattern, initializer: Expr },
+    Const { name: Token, initializer: Expr },
     Block { statements: Vec<Box<Stmt>> },
     IfStmt { predicate: Expr, 
         then: Box<Stmt>, 
         els: Option<Box<Stmt>> 
     },
+    // `for` loops become a while loop whose increment runs after the body,
+    // even when the body ends with `continue`.
     WhileStmt {
         condition: Expr,
         body: Box<Stmt>,
+        increment: Option<Expr>,
     },
+    // The body runs once before the condition is first checked.
+    DoWhile {
+        body: Box<Stmt>,
+        condition: Expr,
+    },
+    Break { keyword: Token },
+    Continue { keyword: Token },
     Function {
         name: Token,
-        params: Vec<Token>,
+        params: Parameters,
         body: Vec<Box<Stmt>>,
+        generator: bool,
+        // The declaration as written, to save the function by.
+        source: Rc<FunctionSource>,
+    },
+    Yield {
+        keyword: Token,
+        value: Expr,
+    },
+    Import {
+        keyword: Token,
+        path: String,
+        alias: Option<Token>,
+        names: Vec<Token>,
+    },
+    Throw {
+        keyword: Token,
+        value: Expr,
+    },
+    Try {
+        body: Box<Stmt>,
+        catch: Option<(Token, Box<Stmt>)>,
+        finally: Option<Box<Stmt>>,
+    },
+    Enum {
+        name: Token,
+        variants: Vec<Token>,
+    },
+    ForIn {
+        target: BindingPattern,
+        iterable: Expr,
+        body: Box<Stmt>,
+    },
+    Match {
+        keyword: Token,
+        subject: Expr,
+        arms: Vec<MatchArm>,
     },
     // ForStmt {
     //     var_decl: Option<Box<Stmt>>,
@@ -30,6 +195,33 @@ pub enum Stmt {
 }
 
 impl Stmt {
+    // Whether running this statement can reach a `yield` or a suspending
+    // `await` of the generator it's in. Nested functions have their own
+    // bodies, so they don't count.
+    pub fn yields(&self) -> bool {
+        use Stmt::*;
+
+        if crate::generator::is_await_statement(self) {
+            return true;
+        }
+
+        match self {
+            Yield { keyword: _, value: _ } => true,
+            Block { statements } => statements.iter().any(|stmt| stmt.yields()),
+            IfStmt { predicate: _, then, els } => then.yields() || els.as_ref().is_some_and(|els| els.yields()),
+            WhileStmt { condition: _, body, increment: _ } => body.yields(),
+            DoWhile { body, condition: _ } => body.yields(),
+            ForIn { target: _, iterable: _, body } => body.yields(),
+            Match { keyword: _, subject: _, arms } => arms.iter().any(|arm| arm.body.yields()),
+            Try { body, catch, finally } => {
+                body.yields()
+                    || catch.as_ref().is_some_and(|(_, handler)| handler.yields())
+                    || finally.as_ref().is_some_and(|finally| finally.yields())
+            },
+            _ => false,
+        }
+    }
+
     #[allow(dead_code)]
     pub fn to_string(&self) -> String {
         use Stmt::*;
@@ -38,6 +230,8 @@ impl Stmt {
             Expression { expression } => expression.to_string(),
             Print { expression } => format!("(print {})", expression.to_string()),
             Var { name, initializer: _ } => format!("(var {})", name.lexeme),
+            Destructure { pattern, initializer } => format!("(var {} {})", pattern.to_string(), initializer.to_string()),
+            Const { name, initializer: _ } => format!("(const {})", name.lexeme),
             Block { statements } => {
                 format!(
                     "(block {})", 
@@ -48,12 +242,53 @@ impl Stmt {
                 )
             }
             IfStmt { predicate: _, then: _, els: _ } => todo!(),
-            WhileStmt { condition: _condition, body: _body } => {
+            WhileStmt { condition: _condition, body: _body, increment: _ } => {
                 todo!()
             },
-            Function { name: _, params: _, body: _ } => {
+            DoWhile { body, condition } => format!("(do {} while {})", body.to_string(), condition.to_string()),
+            Break { keyword: _ } => "(break)".to_string(),
+            Continue { keyword: _ } => "(continue)".to_string(),
+            Yield { keyword: _, value } => format!("(yield {})", value.to_string()),
+            Function { name: _, params: _, body: _, generator: _, source: _ } => {
                 todo!()
             },
+            Import { keyword: _, path, alias: _, names: _ } => format!("(import {})", path),
+            Throw { keyword: _, value } => format!("(throw {})", value.to_string()),
+            Try { body, catch, finally } => {
+                let mut parts = vec![body.to_string()];
+                if let Some((name, handler)) = catch {
+                    parts.push(format!("(catch {} {})", name.lexeme, handler.to_string()));
+                }
+                if let Some(finally) = finally {
+                    parts.push(format!("(finally {})", finally.to_string()));
+                }
+                format!("(try {})", parts.join(" "))
+            },
+            Enum { name, variants } => {
+                let variants: Vec<&str> = variants.iter().map(|v| v.lexeme.as_str()).collect();
+                format!("(enum {} {})", name.lexeme, variants.join(" "))
+            },
+            ForIn { target, iterable, body } => {
+                format!("(for {} in {} {})", target.to_string(), iterable.to_string(), body.to_string())
+            },
+            Match { keyword: _, subject, arms } => {
+                let arms: Vec<String> = arms.iter().map(|arm| {
+                    let pattern = match &arm.pattern {
+                        Pattern::Wildcard => "_".to_string(),
+                        Pattern::Value(value) => value.to_string(),
+                        Pattern::Range { start, end, inclusive } => {
+                            let op = if *inclusive { "..=" } else { ".." };
+                            format!("({} {} {})", op, start.to_string(), end.to_string())
+                        },
+                        Pattern::Binding(name) => name.lexeme.clone(),
+                    };
+                    match &arm.guard {
+                        Some(guard) => format!("({} if {} {})", pattern, guard.to_string(), arm.body.to_string()),
+                        None => format!("({} {})", pattern, arm.body.to_string()),
+                    }
+                }).collect();
+                format!("(match {} {})", subject.to_string(), arms.join(" "))
+            },
             // ForStmt { var_decl, condition, incrementer } => {
             // }
         }
@@ -0,0 +1,267 @@
+use crate::environment::Environment;
+use crate::error::RuntimeError;
+use crate::expr::{CallableFn, LiteralValue, NamedArgs};
+use crate::generator::{Generator, Resumed};
+use crate::native::{native_fn, CallContext};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+// Cooperative tasks. `spawn f()` queues the call; when its turn comes it
+// runs until it finishes or, if `f` is a generator, until it yields or
+// awaits something that isn't ready. Then the next ready task gets a turn.
+// Time is virtual: when nothing is ready the clock jumps straight to the
+// earliest sleeper, so runs are deterministic and `sleep` never blocks.
+// How a task ended, or what an `await` in it evaluates to.
+type Outcome = Result<LiteralValue, RuntimeError>;
+
+#[derive(Default)]
+pub struct Scheduler {
+    now: f64,
+    next_id: usize,
+    // Tasks that can run, each with what its pending `await` evaluates to.
+    ready: VecDeque<(Rc<Task>, Option<Outcome>)>,
+    // Sleeping tasks as (wake time, task), kept in wake order.
+    timers: Vec<(f64, Rc<Task>)>,
+    failed: Vec<Rc<Task>>,
+}
+
+pub struct Task {
+    pub name: String,
+    id: usize,
+    body: RefCell<TaskBody>,
+    outcome: RefCell<Option<Outcome>>,
+    // Whether anyone has seen the outcome; failures nobody awaited are
+    // reported once the event loop runs dry.
+    observed: Cell<bool>,
+    waiters: RefCell<Vec<Rc<Task>>>,
+}
+
+#[derive(Clone)]
+enum TaskBody {
+    Call {
+        fun: CallableFn,
+        args: Vec<LiteralValue>,
+        named: NamedArgs,
+        environment: Rc<RefCell<Environment>>,
+    },
+    Generator(Rc<Generator>),
+    Timer,
+}
+
+impl Task {
+    pub fn is_done(&self) -> bool {
+        self.outcome.borrow().is_some()
+    }
+
+    // The task's return value, or the error it failed with.
+    pub fn result(&self) -> Option<Outcome> {
+        let outcome = self.outcome.borrow().clone();
+        if outcome.is_some() {
+            self.observed.set(true);
+        }
+        outcome
+    }
+}
+
+impl Scheduler {
+    fn task(&mut self, name: &str, body: TaskBody) -> Rc<Task> {
+        self.next_id += 1;
+        Rc::new(Task {
+            name: name.to_string(),
+            id: self.next_id,
+            body: RefCell::new(body),
+            outcome: RefCell::new(None),
+            observed: Cell::new(false),
+            waiters: RefCell::new(vec![]),
+        })
+    }
+
+    fn finish(&mut self, task: &Rc<Task>, result: Outcome) {
+        for waiter in task.waiters.borrow_mut().drain(..) {
+            task.observed.set(true);
+            self.ready.push_back((waiter, Some(result.clone())));
+        }
+        if result.is_err() {
+            self.failed.push(task.clone());
+        }
+        *task.outcome.borrow_mut() = Some(result);
+    }
+
+    // Parks `task` until `value` is ready. Anything that isn't a task is
+    // ready straight away.
+    fn wait_for(&mut self, task: Rc<Task>, value: LiteralValue) {
+        match value {
+            LiteralValue::Task(awaited) => match awaited.result() {
+                Some(result) => self.ready.push_back((task, Some(result))),
+                None => awaited.waiters.borrow_mut().push(task),
+            },
+            value => self.ready.push_back((task, Some(Ok(value)))),
+        }
+    }
+
+    // Wakes the earliest sleeper, moving the clock forward to its time.
+    fn wake_next(&mut self) -> bool {
+        if self.timers.is_empty() {
+            return false;
+        }
+
+        let (time, timer) = self.timers.remove(0);
+        self.now = self.now.max(time);
+        self.finish(&timer, Ok(LiteralValue::Nil));
+        true
+    }
+}
+
+fn scheduler(environment: &Rc<RefCell<Environment>>, line: usize) -> Result<Rc<RefCell<Scheduler>>, RuntimeError> {
+    match environment.borrow().context() {
+        Some(context) => Ok(context.scheduler.clone()),
+        None => Err(RuntimeError::error(line, "tasks are not available here".to_string())),
+    }
+}
+
+pub fn spawn(
+    environment: &Rc<RefCell<Environment>>,
+    name: &str,
+    fun: CallableFn,
+    args: Vec<LiteralValue>,
+    named: NamedArgs,
+    line: usize,
+) -> Result<LiteralValue, RuntimeError> {
+    let scheduler = scheduler(environment, line)?;
+    let mut scheduler = scheduler.borrow_mut();
+    let task = scheduler.task(name, TaskBody::Call {
+        fun,
+        args,
+        named,
+        environment: environment.clone(),
+    });
+    scheduler.ready.push_back((task.clone(), None));
+
+    Ok(LiteralValue::Task(task))
+}
+
+// Gives one ready task a turn. The scheduler isn't borrowed while the task
+// runs, since the task may spawn, sleep or await in turn.
+fn run_slice(scheduler: &Rc<RefCell<Scheduler>>, task: Rc<Task>, input: Option<Outcome>) {
+    let body = task.body.borrow().clone();
+    let generator = match body {
+        TaskBody::Call { fun, args, named, environment } => match fun(environment, &args, &named) {
+            // Calling a generator function only sets it up; the task
+            // carries on as that generator.
+            Ok(LiteralValue::Generator(generator)) => {
+                *task.body.borrow_mut() = TaskBody::Generator(generator.clone());
+                generator
+            },
+            result => return scheduler.borrow_mut().finish(&task, result),
+        },
+        TaskBody::Generator(generator) => generator,
+        TaskBody::Timer => return,
+    };
+
+    let resumed = generator.step(input);
+    let mut scheduler = scheduler.borrow_mut();
+    match resumed {
+        Ok(Resumed::Yielded(_)) => scheduler.ready.push_back((task, None)),
+        Ok(Resumed::Awaiting(value)) => scheduler.wait_for(task, value),
+        Ok(Resumed::Finished(value)) => scheduler.finish(&task, Ok(value)),
+        Err(error) => scheduler.finish(&task, Err(error)),
+    }
+}
+
+// Runs tasks until `target` is done, or until nothing is left to run when
+// there is no target.
+fn run_until(scheduler: &Rc<RefCell<Scheduler>>, target: Option<&Rc<Task>>, line: usize) -> Result<(), RuntimeError> {
+    loop {
+        if target.is_some_and(|target| target.is_done()) {
+            return Ok(());
+        }
+
+        let next = {
+            let mut scheduler = scheduler.borrow_mut();
+            match scheduler.ready.pop_front() {
+                Some(next) => Some(next),
+                None if scheduler.wake_next() => continue,
+                None => None,
+            }
+        };
+
+        match (next, target) {
+            (Some((task, input)), _) => run_slice(scheduler, task, input),
+            (None, Some(target)) => {
+                return Err(RuntimeError::error(line, format!("deadlock: task {} can never finish", target.name)));
+            },
+            (None, None) => return Ok(()),
+        }
+    }
+}
+
+// `await value` outside a task: runs the event loop until `value` is done
+// and returns its result. Anything that isn't a task is already done.
+pub fn block_on(environment: &Rc<RefCell<Environment>>, value: LiteralValue, line: usize) -> Result<LiteralValue, RuntimeError> {
+    let task = match value {
+        LiteralValue::Task(task) => task,
+        value => return Ok(value),
+    };
+
+    let scheduler = scheduler(environment, line)?;
+    run_until(&scheduler, Some(&task), line)?;
+    task.result().expect("run_until returns once the task is done")
+}
+
+// Runs every remaining task. A task that failed without anyone awaiting it
+// reports its error here.
+pub fn run_pending(environment: &Rc<RefCell<Environment>>) -> Result<(), RuntimeError> {
+    let scheduler = scheduler(environment, 0)?;
+    run_until(&scheduler, None, 0)?;
+
+    let failed = std::mem::take(&mut scheduler.borrow_mut().failed);
+    for task in failed {
+        if !task.observed.get() {
+            if let Some(Err(error)) = task.result() {
+                return Err(error);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// sleep(ms): a task that finishes `ms` milliseconds of virtual time from now.
+fn sleep_impl(context: &CallContext, ms: LiteralValue) -> Result<LiteralValue, String> {
+    let ms = match ms {
+        LiteralValue::Number(ms) if ms >= 0.0 => ms as f64,
+        other => return Err(format!("sleep expects a non-negative number of milliseconds, got {}", other.to_string())),
+    };
+
+    let scheduler = context.scheduler()?;
+    let mut scheduler = scheduler.borrow_mut();
+    let wake = scheduler.now + ms;
+    let timer = scheduler.task("sleep", TaskBody::Timer);
+    // After every timer due at or before `wake`, so equal wake times keep
+    // the order they were asked for in.
+    let position = scheduler.timers.partition_point(|(time, _)| *time <= wake);
+    scheduler.timers.insert(position, (wake, timer.clone()));
+
+    Ok(LiteralValue::Task(timer))
+}
+
+// now(): the virtual time in milliseconds.
+fn now_impl(context: &CallContext) -> Result<f64, String> {
+    let scheduler = context.scheduler()?;
+    let now = scheduler.borrow().now;
+    Ok(now)
+}
+
+pub fn natives() -> Vec<(&'static str, LiteralValue)> {
+    vec![
+        ("sleep", native_fn("sleep", sleep_impl)),
+        ("now", native_fn("now", now_impl)),
+    ]
+}
+
+impl std::fmt::Display for Task {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "<task {}#{}>", self.name, self.id)
+    }
+}
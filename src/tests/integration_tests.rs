@@ -1,16 +1,23 @@
-use std::process::{Command, Stdio};
+use crate::{interpreter::Interpreter, lox::Lox, module::Context, output::{Output, OutputBuffer}, parser::Parser, sandbox::Capabilities, Scanner};
 
-use crate::{interpreter::Interpreter, parser::Parser, Scanner};
+// Runs a script the way the command line does and returns everything it
+// printed, followed by the error it stopped with, if any.
+pub fn run_and_capture(path: &str) -> String {
+    let output = OutputBuffer::new();
+    let mut lox = Lox::new();
+    lox.set_capabilities(Capabilities::all());
+    lox.set_stdout(output.clone());
+    lox.set_stderr(output.clone());
 
-pub fn run_and_capture(command: &mut Command) -> std::process::Output {
-    command.output().unwrap()
+    if let Err(e) = lox.run_file(path) {
+        lox.report(&e);
+    }
+    output.contents()
 }
 
 #[test]
 fn interpret_block() {
-    let output = Command::new("./target/debug/cii").args(vec!["./src/tests/cases/block.lox"]).output().unwrap();
-
-    let output = String::from_utf8(output.stdout).unwrap();
+    let output = run_and_capture("./src/tests/cases/block.lox");
     let lines: Vec<&str> = output.split("\n").collect();
 
     assert_eq!(lines.len(), 3);
@@ -20,9 +27,7 @@ fn interpret_block() {
 
 #[test]
 fn interpret_while() {
-    let output = Command::new("./target/debug/cii").args(vec!["./src/tests/cases/while.lox"]).output().unwrap();
-
-    let output = String::from_utf8(output.stdout).unwrap();
+    let output = run_and_capture("./src/tests/cases/while.lox");
     let lines: Vec<&str> = output.split("\n").collect();
 
     assert_eq!(lines[0], "1");
@@ -30,9 +35,7 @@ fn interpret_while() {
 
 #[test]
 fn interpret_while_math() {
-    let output = Command::new("./target/debug/cii").args(vec!["./src/tests/cases/while_math.lox"]).output().unwrap();
-
-    let output = String::from_utf8(output.stdout).unwrap();
+    let output = run_and_capture("./src/tests/cases/while_math.lox");
     let lines: Vec<&str> = output.split("\n").collect();
     assert_eq!(lines.len(), 11);
     assert_eq!(lines[0], "10");
@@ -48,15 +51,13 @@ fn interpret_while_math() {
 
 #[test]
 fn interpret_for() {
-    let output = Command::new("./target/debug/cii").args(vec!["./src/tests/cases/for.lox"]).output().unwrap();
-
-    let output = String::from_utf8(output.stdout).unwrap();
+    let output = run_and_capture("./src/tests/cases/for.lox");
     let lines: Vec<&str> = output.split("\n").collect();
 
     let mut fibo = vec![];
     let mut a = 1;
     let mut b = 1;
-    let mut temp = 0;
+    let mut temp;
     for _ in 0..21 {
         temp = b;
         b = a + b;
@@ -78,8 +79,431 @@ fn test_bug() {
     let source = std::fs::read_to_string("src/tests/cases/while.lox").unwrap();
     let mut scanner = Scanner::new(&source);
     let tokens = scanner.scan_tokens().unwrap();
-    let mut parser = Parser::new(tokens);
+    let mut parser = Parser::new(tokens, &source);
     let statements = parser.parse().unwrap();
-    let mut interpreter = Interpreter::new();
+    let output = OutputBuffer::new();
+    let context = Context::new(None);
+    *context.output.borrow_mut() = Output {
+        stdout: Box::new(output.clone()),
+        stderr: Box::new(output.clone()),
+    };
+    let mut interpreter = Interpreter::with_context(context);
     interpreter.interpret(statements.iter().map(|stmt| stmt).collect()).unwrap();
+    assert_eq!(output.contents(), "1\n0\n");
+}
+
+#[test]
+fn interpret_bitwise() {
+    let output = run_and_capture("./src/tests/cases/bitwise.lox");
+    let lines: Vec<&str> = output.split("\n").collect();
+
+    assert_eq!(lines.len(), 11);
+    assert_eq!(lines[0], "7");
+    assert_eq!(lines[1], "3");
+    assert_eq!(lines[2], "5");
+    assert_eq!(lines[3], "-1");
+    assert_eq!(lines[4], "16");
+    assert_eq!(lines[5], "64");
+    assert_eq!(lines[6], "true");
+    assert_eq!(lines[7], "8388609");
+    assert_eq!(lines[8], "Error at line 10: '|' only works on integers between -16777215 and 16777215, operand -100000000000000000000 is out of range");
+    assert_eq!(lines[9], "ERROR: Line 11: '<<' only works on integers between -16777215 and 16777215, result 16777216 is out of range");
+}
+
+#[test]
+fn interpret_compound_assignment() {
+    let output = run_and_capture("./src/tests/cases/compound.lox");
+    let lines: Vec<&str> = output.split("\n").collect();
+
+    let expected = ["15", "12", "24", "6", "2", "2", "3", "4", "4", "2", "\"ab\"", "0", "1", "2"];
+    assert_eq!(lines.len() - 1, expected.len());
+    for i in 0..expected.len() {
+        assert_eq!(lines[i], expected[i]);
+    }
+}
+
+#[test]
+fn interpret_conditional() {
+    let output = run_and_capture("./src/tests/cases/conditional.lox");
+    let lines: Vec<&str> = output.split("\n").collect();
+
+    let expected = ["\"big\"", "\"medium\"", "\"default\"", "3", "7", "3", "3", "0", "1", "1"];
+    assert_eq!(lines.len() - 1, expected.len());
+    for i in 0..expected.len() {
+        assert_eq!(lines[i], expected[i]);
+    }
+}
+
+#[test]
+fn interpret_lambda() {
+    let output = run_and_capture("./src/tests/cases/lambda.lox");
+    let lines: Vec<&str> = output.split("\n").collect();
+
+    let expected = ["12", "5", "7", "2", "nil", "\"called\"", "\"functions are truthy\"", "\"yes\"", "false", "3"];
+    assert_eq!(lines.len() - 1, expected.len());
+    for i in 0..expected.len() {
+        assert_eq!(lines[i], expected[i]);
+    }
+}
+
+#[test]
+fn interpret_parameters() {
+    let output = run_and_capture("./src/tests/cases/parameters.lox");
+    let lines: Vec<&str> = output.split("\n").collect();
+
+    let expected = [
+        "\"hello ann\"",
+        "\"hi bob\"",
+        "9",
+        "[]",
+        "[2, 3]",
+        "[11, 25, 30]",
+        "30",
+        "greet/1 to 2",
+        "gather/at least 1",
+        "ERROR: Line 25: Callable greet expected 1 to 2 arguments but got 3",
+    ];
+    assert_eq!(lines.len() - 1, expected.len());
+    for i in 0..expected.len() {
+        assert_eq!(lines[i], expected[i]);
+    }
+}
+
+#[test]
+fn interpret_lists() {
+    let output = run_and_capture("./src/tests/cases/lists.lox");
+    let lines: Vec<&str> = output.split("\n").collect();
+
+    let expected = [
+        "[1, \"two\", nil, [3, 4]]",
+        "\"two\"",
+        "3",
+        "[]",
+        "10",
+        "[3, 20]",
+        "[7, 0]",
+        "1",
+        "[[...]]",
+        "true",
+        "true",
+        "false",
+        "ERROR: Line 29: index 2 is out of range for a list of length 2",
+    ];
+    assert_eq!(lines.len() - 1, expected.len());
+    for i in 0..expected.len() {
+        assert_eq!(lines[i], expected[i]);
+    }
+}
+
+#[test]
+fn interpret_maps() {
+    let output = run_and_capture("./src/tests/cases/maps.lox");
+    let lines: Vec<&str> = output.split("\n").collect();
+
+    let expected = [
+        "{name: \"ada\", \"last name\": \"lovelace\"}",
+        "\"ada\"",
+        "\"lovelace\"",
+        "{name: \"ada\", \"last name\": \"lovelace\", age: 36}",
+        "true",
+        "{m: {...}}",
+        "true",
+        "true",
+        "{inner: {m: {...}}}",
+    ];
+    assert_eq!(lines.len() - 1, expected.len());
+    for i in 0..expected.len() {
+        assert_eq!(lines[i], expected[i]);
+    }
+}
+
+#[test]
+fn interpret_named_arguments() {
+    let output = run_and_capture("./src/tests/cases/named_args.lox");
+    let lines: Vec<&str> = output.split("\n").collect();
+
+    let expected = [
+        "[1, 0, 3]",
+        "[2, 0, 5]",
+        "[1, 7, 0]",
+        "6",
+        "ERROR: Line 9: point has no parameter named 'w'",
+    ];
+    assert_eq!(lines.len() - 1, expected.len());
+    for i in 0..expected.len() {
+        assert_eq!(lines[i], expected[i]);
+    }
+}
+
+#[test]
+fn interpret_exceptions() {
+    let output = run_and_capture("./src/tests/cases/exceptions.lox");
+    let lines: Vec<&str> = output.split("\n").collect();
+
+    let expected = [
+        "\"boom\"",
+        "\"'Plus' is not defined for number and string\"",
+        "8",
+        "Error at line 15: Variable 'undefined_variable' has not been declared",
+        "\"cleanup\"",
+        "[3, \"too big\"]",
+        "\"inner\"",
+        "\"outer\"",
+        "\"inner finally\"",
+        "1",
+        "\"Callable fail expected 1 argument but got 2\"",
+        "ERROR: Line 63: uncaught exception \"uncaught\"",
+    ];
+    assert_eq!(lines.len() - 1, expected.len());
+    for i in 0..expected.len() {
+        assert_eq!(lines[i], expected[i]);
+    }
+}
+
+#[test]
+fn interpret_modules() {
+    let output = run_and_capture("./src/tests/cases/modules.lox");
+    let lines: Vec<&str> = output.split("\n").collect();
+
+    let expected = [
+        "\"loading math\"",
+        "16",
+        "9",
+        "3",
+        "12",
+        "<module math>",
+        "1",
+        "\"cannot import 'modules/missing.lox': No such file or directory (os error 2)\"",
+        "\"module 'math' has no binding 'nope'\"",
+        "ERROR: Line 1: import cycle: cycle_a -> cycle_b -> cycle_a",
+    ];
+    assert_eq!(lines.len() - 1, expected.len());
+    for i in 0..expected.len() {
+        assert_eq!(lines[i], expected[i]);
+    }
+}
+
+#[test]
+fn interpret_const() {
+    let output = run_and_capture("./src/tests/cases/const.lox");
+    let lines: Vec<&str> = output.split("\n").collect();
+
+    let expected = [
+        "10",
+        "2",
+        "\"cannot assign to constant 'MAX'\"",
+        "3",
+    ];
+    assert_eq!(lines.len() - 1, expected.len());
+    for i in 0..expected.len() {
+        assert_eq!(lines[i], expected[i]);
+    }
+
+    // Assignments the resolver can see fail before anything runs.
+    let output = run_and_capture("./src/tests/cases/const_static.lox");
+    assert_eq!(output, "ERROR: Line 3: cannot assign to constant 'NAME'\n");
+}
+
+#[test]
+fn interpret_enums() {
+    let output = run_and_capture("./src/tests/cases/enums.lox");
+    let lines: Vec<&str> = output.split("\n").collect();
+
+    let expected = [
+        "Color.Red",
+        "<enum Color>",
+        "true",
+        "true",
+        "\"Blue\"",
+        "2",
+        "false",
+        "\"warm\"",
+        "\"natural\"",
+        "\"cool\"",
+        "\"zero\"",
+        "\"small\"",
+        "\"medium\"",
+        "\"medium\"",
+        "\"negative\"",
+        "\"a word\"",
+        "\"large\"",
+        "6",
+        "\"no match arm for Light.Green\"",
+        "\"Number is not iterable\"",
+    ];
+    assert_eq!(lines.len() - 1, expected.len());
+    for i in 0..expected.len() {
+        assert_eq!(lines[i], expected[i]);
+    }
+}
+
+#[test]
+fn interpret_match() {
+    let output = run_and_capture("./src/tests/cases/match.lox");
+    let lines: Vec<&str> = output.split("\n").collect();
+
+    let expected = [
+        "\"zero\"",
+        "\"one\"",
+        "\"a few\"",
+        "\"a few\"",
+        "\"some\"",
+        "\"big and more\"",
+        "\"negative\"",
+        "\"first\"",
+        "42",
+        "\"outer\"",
+        "\"lox!\"",
+        "\"no match arm for 7\"",
+    ];
+    assert_eq!(lines.len() - 1, expected.len());
+    for i in 0..expected.len() {
+        assert_eq!(lines[i], expected[i]);
+    }
+}
+
+#[test]
+fn interpret_destructuring() {
+    let output = run_and_capture("./src/tests/cases/destructuring.lox");
+    let lines: Vec<&str> = output.split("\n").collect();
+
+    let expected = [
+        "3",
+        "1",
+        "[2, 3, 4]",
+        "\"xyz\"",
+        "[]",
+        "\"loading math\"",
+        "9",
+        "\"index 5 is out of range for a list of length 1\"",
+        "7",
+        "\"one\"",
+        "1",
+        "\"two\"",
+        "2",
+        "\"Red\"",
+        "\"[only] expects 1 items but the list has 2\"",
+        "\"[m, n, ...more] expects at least 2 items but the list has 1\"",
+        "\"cannot destructure String as a list\"",
+        "\"Number has no property 'length'\"",
+    ];
+    assert_eq!(lines.len() - 1, expected.len());
+    for i in 0..expected.len() {
+        assert_eq!(lines[i], expected[i]);
+    }
+}
+
+#[test]
+fn interpret_generators() {
+    let output = run_and_capture("./src/tests/cases/generators.lox");
+    let lines: Vec<&str> = output.split("\n").collect();
+
+    let expected = [
+        "0",
+        "1",
+        "2",
+        "<generator range>",
+        "{value: 0, done: false}",
+        "false",
+        "{value: 1, done: false}",
+        "{value: nil, done: true}",
+        "true",
+        "\"created\"",
+        "\"started\"",
+        "1",
+        "\"resumed\"",
+        "true",
+        "6",
+        "2",
+        "4",
+        "6",
+        "3",
+        "7",
+        "\"before\"",
+        "\"caught oops\"",
+        "\"finally\"",
+        "\"after\"",
+        "1",
+        "\"broken\"",
+        "true",
+        "[1, 0]",
+        "[2, 0]",
+        "[2, 1]",
+        "\"generator selfish is already running\"",
+        "{value: nil, done: false}",
+        "{value: nil, done: true}",
+    ];
+    assert_eq!(lines.len() - 1, expected.len());
+    for i in 0..expected.len() {
+        assert_eq!(lines[i], expected[i]);
+    }
+}
+
+#[test]
+fn interpret_tasks() {
+    let output = run_and_capture("./src/tests/cases/tasks.lox");
+    let lines: Vec<&str> = output.split("\n").collect();
+
+    let expected = [
+        "<task worker#1>",
+        "false",
+        "[\"a\", 0]",
+        "[\"b\", 0]",
+        "[\"a\", 1]",
+        "[\"b\", 1]",
+        "\"a done\"",
+        "false",
+        "[\"b\", 2]",
+        "\"b done\"",
+        "[\"fast\", 100]",
+        "[\"medium\", 200]",
+        "[\"slow\", 300]",
+        "600",
+        "300",
+        "6",
+        "\"caught task failed\"",
+        "Error at line 59: Callable sleep does not take named arguments",
+        "\"inner caught task failed\"",
+        "\"recovered\"",
+        "\"before the loop runs\"",
+        "\"ran later\"",
+        "\"deadlock: task stuck can never finish\"",
+        "\"end of script\"",
+        "ERROR: Line 50: uncaught exception \"task failed\"",
+    ];
+    assert_eq!(lines.len() - 1, expected.len());
+    for i in 0..expected.len() {
+        assert_eq!(lines[i], expected[i]);
+    }
+}
+
+#[test]
+fn interpret_loops() {
+    let output = run_and_capture("./src/tests/cases/loops.lox");
+    let lines: Vec<&str> = output.split("\n").collect();
+
+    let expected = [
+        "3",
+        "\"ran once\"",
+        "1",
+        "3",
+        "0",
+        "2",
+        "3",
+        "1",
+        "3",
+        "[1, 1]",
+        "[2, 1]",
+        "\"finally\"",
+        "2",
+        "4",
+        "6",
+        "3",
+        "2",
+        "1",
+    ];
+    assert_eq!(lines.len() - 1, expected.len());
+    for i in 0..expected.len() {
+        assert_eq!(lines[i], expected[i]);
+    }
 }
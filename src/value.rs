@@ -0,0 +1,337 @@
+use crate::error::RuntimeError;
+use crate::expr::LiteralValue;
+use crate::host::HostObject;
+use std::fmt;
+use std::rc::Rc;
+
+// A Lox value as the host sees it. What it holds is the engine's business:
+// `FromLox` turns it into a Rust value and `IntoLox` makes one. Lists and
+// maps inside it are still shared with the script.
+#[derive(Clone, PartialEq)]
+pub struct LoxValue(pub(crate) LiteralValue);
+
+impl LoxValue {
+    pub fn nil() -> Self {
+        Self(LiteralValue::Nil)
+    }
+
+    pub fn is_nil(&self) -> bool {
+        matches!(self.0, LiteralValue::Nil)
+    }
+
+    // The name scripts' error messages use for its type, such as "Number".
+    pub fn type_name(&self) -> &str {
+        self.0.to_type()
+    }
+
+    // Converts a copy of it, leaving it usable.
+    pub fn to<T: FromLox>(&self) -> Result<T, String> {
+        T::from_lox(self.clone())
+    }
+}
+
+// The way `print` shows it.
+impl fmt::Display for LoxValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0.to_string())
+    }
+}
+
+impl fmt::Debug for LoxValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "LoxValue({})", self.0.to_string())
+    }
+}
+
+// Conversions between Lox values and Rust types, used wherever the host
+// hands values in or takes them out. A failed conversion says what was
+// expected and what the value actually was.
+pub trait FromLox: Sized {
+    fn from_lox(value: LoxValue) -> Result<Self, String>;
+
+    // Whether nil converts, so a native function's caller may leave an
+    // argument of this type out.
+    fn optional() -> bool {
+        false
+    }
+}
+
+// Fails for a value Lox can't hold as it is, such as a whole number that
+// an f32 would round.
+pub trait IntoLox {
+    fn into_lox(self) -> Result<LoxValue, String>;
+}
+
+// What a native function can return: a value, or a `Result` whose error
+// becomes a runtime error at the call.
+pub trait IntoLoxResult {
+    fn into_lox_result(self) -> Result<LoxValue, RuntimeError>;
+}
+
+impl<T: IntoLox> IntoLoxResult for T {
+    fn into_lox_result(self) -> Result<LoxValue, RuntimeError> {
+        self.into_lox().map_err(|e| RuntimeError::error(0, e))
+    }
+}
+
+impl<T: IntoLox, E: std::fmt::Display> IntoLoxResult for Result<T, E> {
+    fn into_lox_result(self) -> Result<LoxValue, RuntimeError> {
+        self.map_err(|e| e.to_string()).and_then(|value| value.into_lox()).map_err(|e| RuntimeError::error(0, e))
+    }
+}
+
+// Numbers are f32s. Fractions are rounded to the nearest one, as they are
+// in scripts, but a whole number that would come back different, such as
+// an id past 2^24, is an error rather than quietly changed.
+pub(crate) fn number(x: f64) -> Result<f32, String> {
+    let converted = x as f32;
+    if x.is_finite() && converted.is_infinite() {
+        return Err(format!("cannot convert {:e} to a number", x));
+    }
+    match x.fract() == 0.0 && converted as f64 != x {
+        true => Err(format!("cannot convert {} to a number exactly", x)),
+        false => Ok(converted),
+    }
+}
+
+pub(crate) fn whole_number(whole: i128) -> Result<f32, String> {
+    let converted = whole as f32;
+    match converted as i128 == whole {
+        true => Ok(converted),
+        false => Err(format!("cannot convert {} to a number exactly", whole)),
+    }
+}
+
+fn mismatch(expected: &str, value: &LiteralValue) -> String {
+    format!("expected {}, got {}", expected, value.to_type())
+}
+
+impl FromLox for LoxValue {
+    fn from_lox(value: LoxValue) -> Result<Self, String> {
+        Ok(value)
+    }
+}
+
+impl FromLox for LiteralValue {
+    fn from_lox(value: LoxValue) -> Result<Self, String> {
+        Ok(value.0)
+    }
+}
+
+// Any value can be discarded.
+impl FromLox for () {
+    fn from_lox(_value: LoxValue) -> Result<Self, String> {
+        Ok(())
+    }
+}
+
+impl FromLox for f64 {
+    fn from_lox(value: LoxValue) -> Result<Self, String> {
+        match value.0 {
+            LiteralValue::Number(x) => Ok(x as f64),
+            other => Err(mismatch("Number", &other)),
+        }
+    }
+}
+
+impl FromLox for f32 {
+    fn from_lox(value: LoxValue) -> Result<Self, String> {
+        match value.0 {
+            LiteralValue::Number(x) => Ok(x),
+            other => Err(mismatch("Number", &other)),
+        }
+    }
+}
+
+impl FromLox for i64 {
+    fn from_lox(value: LoxValue) -> Result<Self, String> {
+        match value.0 {
+            LiteralValue::Number(x) if x.fract() == 0.0 => Ok(x as i64),
+            LiteralValue::Number(x) => Err(format!("expected an integer, got {}", x)),
+            other => Err(mismatch("Number", &other)),
+        }
+    }
+}
+
+impl FromLox for String {
+    fn from_lox(value: LoxValue) -> Result<Self, String> {
+        match value.0 {
+            LiteralValue::StringValue(s) => Ok(s),
+            other => Err(mismatch("String", &other)),
+        }
+    }
+}
+
+impl FromLox for bool {
+    fn from_lox(value: LoxValue) -> Result<Self, String> {
+        match value.0 {
+            LiteralValue::True => Ok(true),
+            LiteralValue::False => Ok(false),
+            other => Err(mismatch("Boolean", &other)),
+        }
+    }
+}
+
+// nil is None, anything else has to convert to T.
+impl<T: FromLox> FromLox for Option<T> {
+    fn from_lox(value: LoxValue) -> Result<Self, String> {
+        match value.0 {
+            LiteralValue::Nil => Ok(None),
+            other => T::from_lox(LoxValue(other)).map(Some),
+        }
+    }
+
+    fn optional() -> bool {
+        true
+    }
+}
+
+impl<T: FromLox> FromLox for Vec<T> {
+    fn from_lox(value: LoxValue) -> Result<Self, String> {
+        match value.0 {
+            LiteralValue::List(items) => {
+                let items = items.borrow().clone();
+                items.into_iter()
+                    .enumerate()
+                    .map(|(i, item)| T::from_lox(LoxValue(item)).map_err(|e| format!("item {}: {}", i, e)))
+                    .collect()
+            },
+            other => Err(mismatch("List", &other)),
+        }
+    }
+}
+
+impl IntoLox for LoxValue {
+    fn into_lox(self) -> Result<LoxValue, String> {
+        Ok(self)
+    }
+}
+
+impl IntoLox for LiteralValue {
+    fn into_lox(self) -> Result<LoxValue, String> {
+        Ok(LoxValue(self))
+    }
+}
+
+impl IntoLox for () {
+    fn into_lox(self) -> Result<LoxValue, String> {
+        Ok(LoxValue(LiteralValue::Nil))
+    }
+}
+
+impl IntoLox for f64 {
+    fn into_lox(self) -> Result<LoxValue, String> {
+        number(self).map(|x| LoxValue(LiteralValue::Number(x)))
+    }
+}
+
+impl IntoLox for f32 {
+    fn into_lox(self) -> Result<LoxValue, String> {
+        Ok(LoxValue(LiteralValue::Number(self)))
+    }
+}
+
+impl IntoLox for i64 {
+    fn into_lox(self) -> Result<LoxValue, String> {
+        whole_number(self as i128).map(|x| LoxValue(LiteralValue::Number(x)))
+    }
+}
+
+impl IntoLox for i32 {
+    fn into_lox(self) -> Result<LoxValue, String> {
+        whole_number(self as i128).map(|x| LoxValue(LiteralValue::Number(x)))
+    }
+}
+
+impl IntoLox for String {
+    fn into_lox(self) -> Result<LoxValue, String> {
+        Ok(LoxValue(LiteralValue::StringValue(self)))
+    }
+}
+
+impl IntoLox for &str {
+    fn into_lox(self) -> Result<LoxValue, String> {
+        Ok(LoxValue(LiteralValue::StringValue(self.to_string())))
+    }
+}
+
+impl IntoLox for bool {
+    fn into_lox(self) -> Result<LoxValue, String> {
+        Ok(LoxValue(LiteralValue::from_bool(self)))
+    }
+}
+
+impl FromLox for Rc<dyn HostObject> {
+    fn from_lox(value: LoxValue) -> Result<Self, String> {
+        match value.0 {
+            LiteralValue::HostObject(object) => Ok(object),
+            other => Err(mismatch("a host object", &other)),
+        }
+    }
+}
+
+impl IntoLox for Rc<dyn HostObject> {
+    fn into_lox(self) -> Result<LoxValue, String> {
+        Ok(LoxValue(LiteralValue::HostObject(self)))
+    }
+}
+
+impl<T: HostObject> IntoLox for Rc<T> {
+    fn into_lox(self) -> Result<LoxValue, String> {
+        Ok(LoxValue(LiteralValue::HostObject(self)))
+    }
+}
+
+impl<T: IntoLox> IntoLox for Option<T> {
+    fn into_lox(self) -> Result<LoxValue, String> {
+        match self {
+            Some(value) => value.into_lox(),
+            None => Ok(LoxValue::nil()),
+        }
+    }
+}
+
+impl<T: IntoLox> IntoLox for Vec<T> {
+    fn into_lox(self) -> Result<LoxValue, String> {
+        let items: Result<Vec<LiteralValue>, String> = self.into_iter()
+            .enumerate()
+            .map(|(i, item)| item.into_lox().map(|item| item.0).map_err(|e| format!("item {}: {}", i, e)))
+            .collect();
+        Ok(LoxValue(LiteralValue::list(items?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        assert_eq!(f64::from_lox(2.5.into_lox().unwrap()), Ok(2.5));
+        assert_eq!(String::from_lox("hi".into_lox().unwrap()), Ok("hi".to_string()));
+        assert_eq!(bool::from_lox(true.into_lox().unwrap()), Ok(true));
+        assert_eq!(Option::<f64>::from_lox(None::<f64>.into_lox().unwrap()), Ok(None));
+        assert_eq!(Vec::<i64>::from_lox(vec![1, 2, 3].into_lox().unwrap()), Ok(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn mismatches() {
+        assert_eq!(f64::from_lox("x".into_lox().unwrap()), Err("expected Number, got String".to_string()));
+        assert_eq!(i64::from_lox(1.5.into_lox().unwrap()), Err("expected an integer, got 1.5".to_string()));
+        assert_eq!(
+            Vec::<bool>::from_lox(vec![LiteralValue::True, LiteralValue::Nil].into_lox().unwrap()),
+            Err("item 1: expected Boolean, got Nil".to_string()),
+        );
+    }
+
+    #[test]
+    fn numbers_that_do_not_fit() {
+        assert_eq!(f64::from_lox(16_777_216i64.into_lox().unwrap()), Ok(16_777_216.0));
+        assert_eq!(16_777_217i64.into_lox(), Err("cannot convert 16777217 to a number exactly".to_string()));
+        assert_eq!(i32::MAX.into_lox(), Err("cannot convert 2147483647 to a number exactly".to_string()));
+        assert_eq!(16_777_217.0f64.into_lox(), Err("cannot convert 16777217 to a number exactly".to_string()));
+        assert_eq!(1e300f64.into_lox(), Err("cannot convert 1e300 to a number".to_string()));
+        assert_eq!(vec![1, (1 << 30) + 1].into_lox(), Err("item 1: cannot convert 1073741825 to a number exactly".to_string()));
+        assert_eq!(f64::from_lox(0.1f64.into_lox().unwrap()), Ok(0.1f32 as f64));
+    }
+}